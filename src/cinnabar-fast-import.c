@@ -28,6 +28,7 @@ static void cinnabar_unregister_shallow(const struct object_id *oid);
 
 static int initialized = 0;
 static int update_shallow = 0;
+static struct blob_stream *current_blob_stream;
 
 void cinnabar_unregister_shallow(const struct object_id *oid) {
 	if (unregister_shallow(oid) == 0)
@@ -210,10 +211,10 @@ static void start_packfile(void)
 	list_add_tail(&pack_data->mru, &the_repository->objects->packed_git_mru);
 }
 
-static void end_packfile(void)
+/* Remove our fake pack window from the list of windows of the pack, so that
+ * close_pack_windows() doesn't try to munmap it. */
+static void detach_pack_window(void)
 {
-	if (prev_win)
-		unuse_pack(&prev_win);
 	if (pack_data) {
 		struct pack_window *win, *prev;
 		for (prev = NULL, win = pack_data->windows;
@@ -227,6 +228,13 @@ static void end_packfile(void)
 			break;
 		}
 	}
+}
+
+static void end_packfile(void)
+{
+	if (prev_win)
+		unuse_pack(&prev_win);
+	detach_pack_window();
 	if (pack_win) {
 		free(pack_win->base);
 		free(pack_win);
@@ -600,9 +608,193 @@ void store_git_object(enum object_type type, const struct strslice buf,
 		reference = NULL;
 	}
 	ENSURE_INIT();
+	if (current_blob_stream)
+		die("Cannot store objects while a blob is being streamed");
 	store_object(type, &data, reference ? &ref_object : NULL, result, 0);
 }
 
+/* After the pack was truncated, our fake pack window, as well as the pack
+ * windows use_pack() created, may cover bytes that are not in the pack
+ * anymore. Drop them all, and set our window up again on the last bits of
+ * the pack, the same way hashwrite() would have. */
+static void reset_pack_windows(void)
+{
+	size_t len;
+
+	if (prev_win)
+		unuse_pack(&prev_win);
+	detach_pack_window();
+	close_pack_windows(pack_data);
+
+	pack_data->pack_size = pack_size + 20;
+	if (pack_size < packed_git_window_size)
+		pack_win->offset = 0;
+	else
+		pack_win->offset = (pack_size / packed_git_window_size)
+			* packed_git_window_size - 20;
+	len = pack_size - pack_win->offset;
+	if (pread_in_full(pack_data->pack_fd, pack_win->base, len,
+	                  pack_win->offset) != (ssize_t)len)
+		die_errno("cannot read pack");
+	pack_win->len = len + 20;
+	pack_data->windows = pack_win;
+
+	if (pack_win->offset) {
+		use_pack(pack_data, &prev_win,
+			 pack_win->offset + 20 - packed_git_window_size, NULL);
+		assert(prev_win->len == packed_git_window_size);
+	}
+}
+
+/* Mostly copied from fast-import.c's stream_blob(), except the data is
+ * given with calls to store_git_blob_stream_write() instead of being read
+ * from stdin. */
+struct blob_stream {
+	uintmax_t len;
+	git_hash_ctx ctx;
+	git_zstream s;
+	struct hashfile_checkpoint checkpoint;
+	off_t offset;
+	unsigned char out_buf[64 * 1024];
+};
+
+struct blob_stream *store_git_blob_stream_start(size_t len)
+{
+	struct blob_stream *stream;
+	unsigned long hdrlen;
+
+	ENSURE_INIT();
+	if (current_blob_stream)
+		die("Cannot stream two blobs at the same time");
+
+	/* Determine if we should auto-checkpoint. */
+	if ((max_packsize
+		&& (pack_size + PACK_SIZE_THRESHOLD + len) > max_packsize)
+		|| (pack_size + PACK_SIZE_THRESHOLD + len) < pack_size)
+		cycle_packfile();
+
+	stream = xcalloc(1, sizeof(*stream));
+	stream->len = len;
+
+	the_hash_algo->init_fn(&stream->checkpoint.ctx);
+	hashfile_checkpoint(pack_file, &stream->checkpoint);
+	stream->offset = stream->checkpoint.offset;
+
+	hdrlen = format_object_header((char *)stream->out_buf,
+	                              sizeof(stream->out_buf), OBJ_BLOB, len);
+
+	the_hash_algo->init_fn(&stream->ctx);
+	the_hash_algo->update_fn(&stream->ctx, stream->out_buf, hdrlen);
+
+	crc32_begin(pack_file);
+
+	git_deflate_init(&stream->s, pack_compression_level);
+
+	hdrlen = encode_in_pack_object_header(stream->out_buf,
+	                                      sizeof(stream->out_buf),
+	                                      OBJ_BLOB, len);
+
+	stream->s.next_out = stream->out_buf + hdrlen;
+	stream->s.avail_out = sizeof(stream->out_buf) - hdrlen;
+
+	current_blob_stream = stream;
+	return stream;
+}
+
+static void blob_stream_deflate(struct blob_stream *stream, int flush)
+{
+	int status = Z_OK;
+
+	while (stream->s.avail_in || (flush && status != Z_STREAM_END)) {
+		status = git_deflate(&stream->s, flush);
+
+		if (!stream->s.avail_out || status == Z_STREAM_END) {
+			size_t n = stream->s.next_out - stream->out_buf;
+			hashwrite(pack_file, stream->out_buf, n);
+			pack_size += n;
+			stream->s.next_out = stream->out_buf;
+			stream->s.avail_out = sizeof(stream->out_buf);
+		}
+
+		switch (status) {
+		case Z_OK:
+		case Z_BUF_ERROR:
+		case Z_STREAM_END:
+			continue;
+		default:
+			die("unexpected deflate failure: %d", status);
+		}
+	}
+}
+
+void store_git_blob_stream_write(struct blob_stream *stream,
+                                 struct strslice buf)
+{
+	if (buf.len > stream->len)
+		die("Too much data for blob (%" PRIuMAX " bytes remaining)",
+		    stream->len);
+
+	the_hash_algo->update_fn(&stream->ctx, buf.buf, buf.len);
+	stream->s.next_in = (unsigned char *)buf.buf;
+	stream->s.avail_in = buf.len;
+	stream->len -= buf.len;
+	blob_stream_deflate(stream, 0);
+}
+
+void store_git_blob_stream_finish(struct blob_stream *stream,
+                                  struct object_id *result)
+{
+	struct object_entry *e;
+
+	if (stream->len)
+		die("Missing data for blob (%" PRIuMAX " bytes remaining)",
+		    stream->len);
+
+	blob_stream_deflate(stream, Z_FINISH);
+	git_deflate_end(&stream->s);
+	the_hash_algo->final_oid_fn(result, &stream->ctx);
+
+	e = insert_object(result);
+
+	if (e->idx.offset) {
+		duplicate_count_by_type[OBJ_BLOB]++;
+		truncate_pack(&stream->checkpoint);
+		reset_pack_windows();
+
+	} else if (find_sha1_pack(result->hash,
+				  get_all_packs(the_repository))) {
+		e->type = OBJ_BLOB;
+		e->pack_id = MAX_PACK_ID;
+		e->idx.offset = 1; /* just not zero! */
+		duplicate_count_by_type[OBJ_BLOB]++;
+		truncate_pack(&stream->checkpoint);
+		reset_pack_windows();
+
+	} else {
+		e->depth = 0;
+		e->type = OBJ_BLOB;
+		e->pack_id = pack_id;
+		e->idx.offset = stream->offset;
+		e->idx.crc32 = crc32_end(pack_file);
+		object_count++;
+		object_count_by_type[OBJ_BLOB]++;
+	}
+
+	current_blob_stream = NULL;
+	free(stream);
+}
+
+/* Removes what was written of a blob that won't be finished from the pack. */
+void store_git_blob_stream_abort(struct blob_stream *stream)
+{
+	git_deflate_end(&stream->s);
+	truncate_pack(&stream->checkpoint);
+	reset_pack_windows();
+
+	current_blob_stream = NULL;
+	free(stream);
+}
+
 const struct object_id empty_blob = { {
 	0xe6, 0x9d, 0xe2, 0x9b, 0xb2, 0xd1, 0xd6, 0x43, 0x4b, 0x8b,
 	0x29, 0xae, 0x77, 0x5a, 0xd8, 0xc2, 0xe4, 0x8c, 0x53, 0x91,
@@ -28,6 +28,18 @@ void store_git_commit(struct strslice commit_buf, struct object_id *result);
 
 void store_git_blob(struct strslice blob_buf, struct object_id *result);
 
+struct blob_stream;
+
+struct blob_stream *store_git_blob_stream_start(size_t len);
+
+void store_git_blob_stream_write(struct blob_stream *stream,
+                                 struct strslice buf);
+
+void store_git_blob_stream_finish(struct blob_stream *stream,
+                                  struct object_id *result);
+
+void store_git_blob_stream_abort(struct blob_stream *stream);
+
 void store_git_object(enum object_type type, const struct strslice buf,
                       struct object_id *result, const struct strslice *reference,
                       const struct object_entry *reference_entry);
@@ -3,19 +3,24 @@
  * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
 
 use std::collections::BTreeMap;
+use std::ffi::OsStr;
+use std::fmt;
 use std::os::raw::c_uint;
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Mutex;
 
 use bstr::ByteSlice;
+use itertools::Itertools;
 
 use crate::cinnabar::GitChangesetId;
+use crate::get_config;
 use crate::git::{CommitId, TreeId};
 use crate::hg::HgChangesetId;
 use crate::hg_data::{GitAuthorship, HgAuthorship};
-use crate::libgit::{lookup_replace_commit, rev_list, RawCommit};
+use crate::libgit::{die, get_oid_blob, lookup_replace_commit, rev_list, RawBlob, RawCommit};
 use crate::progress::Progress;
 use crate::store::{has_metadata, GeneratedGitChangesetMetadata, RawHgChangeset, Store};
+use crate::util::{FromBytes, OsStrExt};
 
 extern "C" {
     fn replace_map_size() -> c_uint;
@@ -30,6 +35,8 @@ static DID_SOMETHING: AtomicBool = AtomicBool::new(false);
 
 static GRAFT_TREES: Mutex<BTreeMap<TreeId, Vec<CommitId>>> = Mutex::new(BTreeMap::new());
 
+static GRAFT_HINTS: Mutex<BTreeMap<HgChangesetId, CommitId>> = Mutex::new(BTreeMap::new());
+
 pub fn graft_finish() -> Option<bool> {
     if GRAFT_TREES.lock().unwrap().is_empty() {
         None
@@ -50,6 +57,15 @@ pub fn init_graft(store: &Store) {
         args.push("--not");
         args.push("refs/cinnabar/metadata^");
     }
+    if let Some(source) = get_config("graft-hints") {
+        *GRAFT_HINTS.lock().unwrap() = read_graft_hints(&source).unwrap_or_else(|e| {
+            die!(
+                "Could not read graft hints from {}: {}",
+                source.to_string_lossy(),
+                e
+            )
+        });
+    }
     let mut graft_trees = GRAFT_TREES.lock().unwrap();
     for cid in rev_list(&args).progress(|n| format!("Reading {} graft candidates", n)) {
         let c = RawCommit::read(cid).unwrap();
@@ -59,12 +75,155 @@ pub fn init_graft(store: &Store) {
     }
 }
 
+// Reads the graft hints configured with `cinnabar.graft-hints`, which is
+// either the path to a file, or a git blob expression such as
+// `refs/graft-hints:hints`.
+fn read_graft_hints(source: &OsStr) -> Result<BTreeMap<HgChangesetId, CommitId>, String> {
+    let buf = match std::fs::read(source) {
+        Ok(buf) => buf,
+        Err(e) => get_oid_blob(source.as_bytes())
+            .and_then(RawBlob::read)
+            .ok_or_else(|| e.to_string())?
+            .as_bytes()
+            .to_vec(),
+    };
+    parse_graft_hints(&buf)
+}
+
+// Each line of graft hints contains a mercurial changeset id and the git
+// commit id it should be grafted onto, separated with whitespace. Empty
+// lines and lines starting with `#` are ignored.
+fn parse_graft_hints(buf: &[u8]) -> Result<BTreeMap<HgChangesetId, CommitId>, String> {
+    let mut hints = BTreeMap::new();
+    for (n, line) in ByteSlice::lines(buf).enumerate() {
+        let line = line.trim_with(|b| b.is_ascii_whitespace());
+        if line.is_empty() || line.starts_with(b"#") {
+            continue;
+        }
+        let (changeset, commit) = line
+            .split(u8::is_ascii_whitespace)
+            .filter(|field| !field.is_empty())
+            .collect_tuple()
+            .and_then(|(changeset, commit)| {
+                Some((
+                    HgChangesetId::from_bytes(changeset).ok()?,
+                    CommitId::from_bytes(commit).ok()?,
+                ))
+            })
+            .ok_or_else(|| format!("invalid line {}: {}", n + 1, line.as_bstr()))?;
+        if let Some(previous) = hints.insert(changeset, commit) {
+            if previous != commit {
+                return Err(format!("conflicting hints for {changeset}"));
+            }
+        }
+    }
+    Ok(hints)
+}
+
+#[test]
+fn test_parse_graft_hints() {
+    let changeset1 = HgChangesetId::from_raw_bytes_array([0x12; 20]);
+    let changeset2 = HgChangesetId::from_raw_bytes_array([0x34; 20]);
+    let commit1 = CommitId::from_raw_bytes_array([0xab; 20]);
+    let commit2 = CommitId::from_raw_bytes_array([0xcd; 20]);
+
+    let hints = parse_graft_hints(
+        b"# changeset commit\n\
+          1212121212121212121212121212121212121212 abababababababababababababababababababab\n\
+          \n\
+          3434343434343434343434343434343434343434\tcdcdcdcdcdcdcdcdcdcdcdcdcdcdcdcdcdcdcdcd  \n\
+          1212121212121212121212121212121212121212 abababababababababababababababababababab",
+    )
+    .unwrap();
+    assert_eq!(
+        hints.into_iter().collect_vec(),
+        vec![(changeset1, commit1), (changeset2, commit2)]
+    );
+    assert_eq!(parse_graft_hints(b"").unwrap().len(), 0);
+
+    assert_eq!(
+        parse_graft_hints(b"\n1212121212121212121212121212121212121212\n"),
+        Err("invalid line 2: 1212121212121212121212121212121212121212".to_string())
+    );
+    assert_eq!(
+        parse_graft_hints(b"1212 abababababababababababababababababababab"),
+        Err("invalid line 1: 1212 abababababababababababababababababababab".to_string())
+    );
+    assert!(parse_graft_hints(
+        b"1212121212121212121212121212121212121212 abababababababababababababababababababab \
+          cdcdcdcdcdcdcdcdcdcdcdcdcdcdcdcdcdcdcdcd"
+    )
+    .is_err());
+    assert_eq!(
+        parse_graft_hints(
+            b"1212121212121212121212121212121212121212 abababababababababababababababababababab\n\
+              1212121212121212121212121212121212121212 cdcdcdcdcdcdcdcdcdcdcdcdcdcdcdcdcdcdcdcd"
+        ),
+        Err(format!("conflicting hints for {changeset1}"))
+    );
+}
+
 #[derive(Debug)]
 pub enum GraftError {
-    Ambiguous(Box<[CommitId]>),
+    /// The changeset could be grafted onto any of the candidate commits.
+    Ambiguous {
+        changeset: HgChangesetId,
+        candidates: Box<[CommitId]>,
+    },
+    /// The changeset is ambiguous, and the graft hint for it is not one of
+    /// the candidate commits.
+    InvalidHint {
+        changeset: HgChangesetId,
+        hint: CommitId,
+        candidates: Box<[CommitId]>,
+    },
     NoGraft,
 }
 
+impl fmt::Display for GraftError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GraftError::Ambiguous {
+                changeset,
+                candidates,
+            } => write!(
+                f,
+                "Cannot graft {changeset}. Candidates: {}",
+                itertools::join(candidates.iter(), ", ")
+            ),
+            GraftError::InvalidHint {
+                changeset,
+                hint,
+                candidates,
+            } => write!(
+                f,
+                "Cannot graft {changeset} onto {hint}, which is not a candidate. \
+                 Candidates: {}",
+                itertools::join(candidates.iter(), ", ")
+            ),
+            GraftError::NoGraft => f.write_str("No graft candidate"),
+        }
+    }
+}
+
+#[test]
+fn test_graft_error_display() {
+    let err = GraftError::Ambiguous {
+        changeset: HgChangesetId::from_raw_bytes_array([0xab; 20]),
+        candidates: vec![
+            CommitId::from_raw_bytes_array([0x12; 20]),
+            CommitId::from_raw_bytes_array([0x34; 20]),
+        ]
+        .into(),
+    };
+    assert_eq!(
+        err.to_string(),
+        "Cannot graft abababababababababababababababababababab. Candidates: \
+         1212121212121212121212121212121212121212, \
+         3434343434343434343434343434343434343434"
+    );
+}
+
 pub fn graft(
     store: &Store,
     changeset_id: HgChangesetId,
@@ -110,6 +269,21 @@ pub fn graft(
         .collect::<Vec<_>>();
     let mut candidates = candidates.iter().collect::<Vec<_>>();
 
+    // When there are multiple candidates, a graft hint, if there is one,
+    // decides which one to use.
+    if candidates.len() > 1 {
+        if let Some(&hint) = GRAFT_HINTS.lock().unwrap().get(&changeset_id) {
+            if !candidates.iter().any(|(cid, _)| **cid == hint) {
+                return Err(GraftError::InvalidHint {
+                    changeset: changeset_id,
+                    hint,
+                    candidates: candidates.iter().map(|(cid, _)| **cid).collect(),
+                });
+            }
+            candidates.retain(|(cid, _)| **cid == hint);
+        }
+    }
+
     if candidates.len() > 1 {
         // Ideally, this should all be tried with fuzziness, and
         // independently of the number of nodes we got, but the
@@ -153,12 +327,13 @@ pub fn graft(
             Ok(Some(*(*commit)))
         }
         0 => Err(GraftError::NoGraft),
-        _ => Err(GraftError::Ambiguous(
-            candidates
+        _ => Err(GraftError::Ambiguous {
+            changeset: changeset_id,
+            candidates: candidates
                 .into_iter()
                 .map(|(cid, _)| *(*cid))
                 .collect::<Vec<_>>()
                 .into(),
-        )),
+        }),
     }
 }
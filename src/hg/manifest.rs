@@ -88,25 +88,90 @@ impl AsRef<[u8]> for RawHgManifest {
 #[derive(Debug)]
 pub struct MalformedManifest;
 
+/// An entry in a raw Mercurial manifest, borrowing from the manifest data.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RawManifestEntry<'a> {
+    path: &'a BStr,
+    node: HgFileId,
+    flags: HgFileAttr,
+}
+
+impl<'a> RawManifestEntry<'a> {
+    /// Path of the file.
+    pub fn path(&self) -> &'a BStr {
+        self.path
+    }
+
+    /// Object Id of the file.
+    pub fn node(&self) -> HgFileId {
+        self.node
+    }
+
+    /// File attribute (regular, executable or symbolic link).
+    pub fn flags(&self) -> HgFileAttr {
+        self.flags
+    }
+
+    fn parse(buf: &mut &'a [u8]) -> Result<Self, MalformedManifest> {
+        let data: &'a [u8] = buf;
+        (|| {
+            let [path, remainder] = data.splitn_exact(b'\0')?;
+            let node = HgFileId::from_bytes(remainder.get(..40)?).ok()?;
+            let [flags, remainder] = remainder[40..].splitn_exact(b'\n')?;
+            *buf = remainder;
+            Some(RawManifestEntry {
+                path: path.as_bstr(),
+                node,
+                flags: HgFileAttr::from_bytes(flags).ok()?,
+            })
+        })()
+        .ok_or(MalformedManifest)
+    }
+}
+
+/// Iterator over the entries of a raw Mercurial manifest.
+///
+/// Iteration stops after the first malformed entry.
+pub struct RawManifestIter<'a> {
+    buf: &'a [u8],
+}
+
+impl<'a> Iterator for RawManifestIter<'a> {
+    type Item = Result<RawManifestEntry<'a>, MalformedManifest>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.buf.is_empty() {
+            return None;
+        }
+        let result = RawManifestEntry::parse(&mut self.buf);
+        if result.is_err() {
+            self.buf = &[];
+        }
+        Some(result)
+    }
+}
+
+impl RawHgManifest {
+    /// Iterates over the manifest entries without copying their paths.
+    pub fn iter(&self) -> RawManifestIter {
+        RawManifestIter { buf: self.as_ref() }
+    }
+}
+
 impl ParseTree for RawHgManifest {
     type Inner = ManifestEntry;
     type Error = MalformedManifest;
 
     fn parse_one_entry(buf: &mut &[u8]) -> Result<WithPath<Self::Inner>, Self::Error> {
-        (|| {
-            let [path, remainder] = buf.splitn_exact(b'\0')?;
-            let fid = HgFileId::from_bytes(&remainder[..40]).ok()?;
-            let [mode, remainder] = remainder[40..].splitn_exact(b'\n')?;
-            *buf = remainder;
-            Some(WithPath::new(
-                path,
+        RawManifestEntry::parse(buf).map(|entry| {
+            WithPath::new(
+                entry.path().as_bytes(),
                 ManifestEntry {
-                    fid,
-                    attr: HgFileAttr::from_bytes(mode).ok()?,
+                    fid: entry.node(),
+                    attr: entry.flags(),
                 },
-            ))
-        })()
-        .ok_or(MalformedManifest)
+            )
+        })
     }
 
     fn write_one_entry<W: Write>(entry: &WithPath<Self::Inner>, mut w: W) -> io::Result<()> {
@@ -127,3 +192,48 @@ impl IntoIterator for RawHgManifest {
         TreeIter::new(self)
     }
 }
+
+#[test]
+fn test_raw_manifest_iter() {
+    let fid = |n| HgFileId::from_raw_bytes_array([n; 20]);
+    let data = format!("bar\0{}\nbaz\0{}x\nfoo/qux\0{}l\n", fid(1), fid(2), fid(3));
+    let entries = RawManifestIter {
+        buf: data.as_bytes(),
+    }
+    .collect::<Result<Vec<_>, _>>()
+    .unwrap();
+    let expected = [
+        ("bar", fid(1), HgFileAttr::Regular),
+        ("baz", fid(2), HgFileAttr::Executable),
+        ("foo/qux", fid(3), HgFileAttr::Symlink),
+    ];
+    assert_eq!(entries.len(), expected.len());
+    for (entry, (path, node, flags)) in entries.iter().zip(expected) {
+        assert_eq!(entry.path(), path.as_bytes().as_bstr());
+        assert_eq!(entry.node(), node);
+        assert_eq!(entry.flags(), flags);
+    }
+
+    // Mercurial never combines flags, and doesn't know about others.
+    for flags in ["xl", "lx", "t", "X"] {
+        let data = format!("bar\0{}{flags}\nbaz\0{}\n", fid(1), fid(2));
+        let mut iter = RawManifestIter {
+            buf: data.as_bytes(),
+        };
+        assert!(iter.next().unwrap().is_err());
+        assert!(iter.next().is_none());
+    }
+
+    for data in [
+        "bar",
+        "bar\0",
+        "bar\x000123",
+        format!("bar\0{}", fid(1)).as_str(),
+    ] {
+        let mut iter = RawManifestIter {
+            buf: data.as_bytes(),
+        };
+        assert!(iter.next().unwrap().is_err());
+        assert!(iter.next().is_none());
+    }
+}
@@ -5,7 +5,6 @@
 use std::cell::Cell;
 use std::collections::{BTreeMap, HashMap};
 use std::fmt::Display;
-use std::fs::File;
 use std::io::{self, copy, Chain, Cursor, ErrorKind, Read, Write};
 use std::iter::repeat;
 use std::mem;
@@ -27,13 +26,13 @@ use tee::TeeReader;
 use zstd::stream::read::Decoder as ZstdDecoder;
 use zstd::stream::write::Encoder as ZstdEncoder;
 
-use crate::get_changes;
+use crate::cinnabar::GitChangesetId;
 use crate::git::CommitId;
 use crate::hg::{HgChangesetId, HgFileId, HgManifestId, HgObjectId};
 use crate::hg_connect::{encodecaps, HgConnection, HgConnectionBase, HgRepo};
 use crate::hg_data::find_file_parents;
 use crate::libcinnabar::{hg_object_id, strslice, AsStrSlice};
-use crate::libgit::{die, RawCommit};
+use crate::libgit::{die, rev_list, RawCommit};
 use crate::oid::ObjectId;
 use crate::progress::Progress;
 use crate::store::{
@@ -42,6 +41,7 @@ use crate::store::{
 use crate::tree_util::{Empty, WithPath};
 use crate::util::{FromBytes, ImmutBString, ReadExt, SliceExt, ToBoxed};
 use crate::xdiff::textdiff;
+use crate::{get_changes, get_config};
 
 #[no_mangle]
 pub unsafe extern "C" fn rev_diff_start_iter(iterator: *mut strslice, chunk: *const rev_chunk) {
@@ -334,6 +334,19 @@ impl FromStr for BundleSpec {
     }
 }
 
+impl BundleSpec {
+    /// Returns the changegroup version used with this bundle type.
+    pub fn changegroup_version(&self) -> u8 {
+        match self {
+            BundleSpec::ChangegroupV1
+            | BundleSpec::V1None
+            | BundleSpec::V1Gzip
+            | BundleSpec::V1Bzip => 1,
+            BundleSpec::V2None | BundleSpec::V2Gzip | BundleSpec::V2Bzip | BundleSpec::V2Zstd => 2,
+        }
+    }
+}
+
 impl Display for BundleSpec {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         let value = match self {
@@ -362,6 +375,91 @@ impl TryFrom<&[u8]> for BundleSpec {
     }
 }
 
+/// Compression settings for zstd bundles.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct BundleCompression {
+    pub level: Option<i32>,
+    pub threads: Option<u32>,
+}
+
+impl FromStr for BundleCompression {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut result = BundleCompression::default();
+        for item in s.split(',').filter(|item| !item.is_empty()) {
+            let [key, value] = item
+                .splitn_exact('=')
+                .ok_or_else(|| format!("invalid compression setting: {}", item))?;
+            match key {
+                "level" => {
+                    result.level = Some(
+                        i32::from_str(value)
+                            .ok()
+                            .filter(|level| zstd::compression_level_range().contains(level))
+                            .ok_or_else(|| format!("invalid zstd compression level: {}", value))?,
+                    );
+                }
+                "threads" => {
+                    result.threads = Some(
+                        u32::from_str(value)
+                            .ok()
+                            .filter(|&threads| threads > 0)
+                            .ok_or_else(|| format!("invalid number of threads: {}", value))?,
+                    );
+                }
+                _ => return Err(format!("unknown compression setting: {}", key)),
+            }
+        }
+        Ok(result)
+    }
+}
+
+impl BundleCompression {
+    /// Reads the settings from the `bundle-compression` configuration, of
+    /// the form `level=<level>,threads=<threads>`, where both items are
+    /// optional. Invalid settings are ignored, with a warning.
+    pub fn from_config() -> Self {
+        get_config("bundle-compression").map_or_else(BundleCompression::default, |config| {
+            config
+                .to_str()
+                .ok_or_else(|| format!("invalid compression settings: {:?}", config))
+                .and_then(BundleCompression::from_str)
+                .unwrap_or_else(|e| {
+                    warn!(target: "root", "Ignoring bundle-compression: {}", e);
+                    BundleCompression::default()
+                })
+        })
+    }
+}
+
+#[test]
+fn test_bundle_compression() {
+    assert_eq!(
+        BundleCompression::from_str(""),
+        Ok(BundleCompression::default())
+    );
+    assert_eq!(
+        BundleCompression::from_str("level=19"),
+        Ok(BundleCompression {
+            level: Some(19),
+            threads: None
+        })
+    );
+    assert_eq!(
+        BundleCompression::from_str("threads=4,level=-5"),
+        Ok(BundleCompression {
+            level: Some(-5),
+            threads: Some(4)
+        })
+    );
+    assert!(BundleCompression::from_str("level=100").is_err());
+    assert!(BundleCompression::from_str("level=fast").is_err());
+    assert!(BundleCompression::from_str("threads=0").is_err());
+    assert!(BundleCompression::from_str("level").is_err());
+    assert!(BundleCompression::from_str("window=27").is_err());
+}
+
 pub struct BundleReader<'a> {
     reader: Chain<Cursor<ImmutBString>, Box<dyn Read + 'a>>,
     version: BundleVersion,
@@ -487,7 +585,54 @@ impl<'a> BundleReader<'a> {
     }
 }
 
-#[derive(Debug, Eq, PartialEq)]
+/// Checks that `reader` contains a mercurial bundle: it must start with a
+/// bundle signature and, for bundle2, all its parts must be properly framed.
+/// Part contents are skipped rather than parsed.
+pub fn check_bundle(mut reader: impl Read) -> io::Result<()> {
+    let mut header = [0; 4];
+    reader.read_exact(&mut header)?;
+    if !matches!(&header, b"HG20" | b"HG10") {
+        return Err(io::Error::new(
+            ErrorKind::InvalidData,
+            "Missing mercurial bundle signature",
+        ));
+    }
+    let mut bundle = BundleReader::new(Cursor::new(header).chain(reader))?;
+    if bundle.version == BundleVersion::V2 {
+        while bundle.next_part()?.is_some() {}
+    }
+    Ok(())
+}
+
+#[test]
+fn test_check_bundle() {
+    for spec in [
+        BundleSpec::V1None,
+        BundleSpec::V1Gzip,
+        BundleSpec::V2None,
+        BundleSpec::V2Gzip,
+    ] {
+        let mut buf = Vec::new();
+        {
+            let mut writer = BundleWriter::new(spec.clone(), &mut buf).unwrap();
+            let info = BundlePartInfo::new(0, "changegroup").set_param("version", "01");
+            let mut part = writer.new_part(info).unwrap();
+            part.write_all(&[0; 12]).unwrap();
+        }
+        assert!(check_bundle(&buf[..]).is_ok(), "{}", spec);
+        if matches!(spec, BundleSpec::V2None) {
+            assert!(check_bundle(&buf[..buf.len() - 2]).is_err());
+        }
+        let mut corrupted = buf.clone();
+        corrupted[..4].copy_from_slice(b"HG30");
+        assert!(check_bundle(&corrupted[..]).is_err(), "{}", spec);
+    }
+    assert!(check_bundle(&b"HG20"[..]).is_err());
+    assert!(check_bundle(&b"HG10XX"[..]).is_err());
+    assert!(check_bundle(&b""[..]).is_err());
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
 pub struct BundlePartInfo {
     pub mandatory: bool,
     pub part_type: Box<str>,
@@ -642,7 +787,15 @@ pub struct BundleWriter<'a> {
 }
 
 impl<'a> BundleWriter<'a> {
-    pub fn new(spec: BundleSpec, mut writer: impl Write + 'a) -> io::Result<Self> {
+    pub fn new(spec: BundleSpec, writer: impl Write + 'a) -> io::Result<Self> {
+        BundleWriter::new_with_compression(spec, writer, BundleCompression::default())
+    }
+
+    pub fn new_with_compression(
+        spec: BundleSpec,
+        mut writer: impl Write + 'a,
+        compression: BundleCompression,
+    ) -> io::Result<Self> {
         match spec {
             BundleSpec::ChangegroupV1 => { /* No header */ }
             BundleSpec::V1None => writer.write_all(b"HG10UN")?,
@@ -663,7 +816,13 @@ impl<'a> BundleWriter<'a> {
             BundleSpec::V1Bzip | BundleSpec::V2Bzip => {
                 Box::new(BzEncoder::new(writer, bzip2::Compression::default()))
             }
-            BundleSpec::V2Zstd => Box::from(ZstdEncoder::new(writer, 0).unwrap()),
+            BundleSpec::V2Zstd => {
+                let mut encoder = ZstdEncoder::new(writer, compression.level.unwrap_or(0))?;
+                if let Some(threads) = compression.threads {
+                    encoder.multithread(threads)?;
+                }
+                Box::from(encoder)
+            }
         };
         Ok(BundleWriter {
             writer,
@@ -842,6 +1001,77 @@ fn test_bundle_part_writer() {
     );
 }
 
+#[test]
+fn test_bundle_v1_roundtrip() {
+    let node = |n| HgObjectId::from_raw_bytes(&[n; 20]).unwrap();
+    let mut changegroup = Vec::new();
+    for (n, p1, data) in [(1, 0, &b"foo\n"[..]), (2, 1, &b"bar\n"[..])] {
+        let mut chunk = Vec::new();
+        chunk.extend_from_slice(node(n).as_raw_bytes());
+        chunk.extend_from_slice(node(p1).as_raw_bytes());
+        chunk.extend_from_slice(HgObjectId::NULL.as_raw_bytes());
+        chunk.extend_from_slice(node(n).as_raw_bytes());
+        chunk.write_u32::<BigEndian>(0).unwrap();
+        chunk.write_u32::<BigEndian>(0).unwrap();
+        chunk.write_u32::<BigEndian>(data.len() as u32).unwrap();
+        chunk.extend_from_slice(data);
+        changegroup
+            .write_u32::<BigEndian>(chunk.len() as u32 + 4)
+            .unwrap();
+        changegroup.extend_from_slice(&chunk);
+    }
+    changegroup.write_u32::<BigEndian>(0).unwrap();
+
+    for (spec, header) in [
+        (BundleSpec::V1None, b"HG10UN"),
+        (BundleSpec::V1Gzip, b"HG10GZ"),
+    ] {
+        let mut buf = Vec::new();
+        {
+            let mut writer = BundleWriter::new(spec.clone(), &mut buf).unwrap();
+            let info = BundlePartInfo::new(0, "changegroup").set_param("version", "01");
+            let mut part = writer.new_part(info).unwrap();
+            part.write_all(&changegroup).unwrap();
+        }
+        assert_eq!(buf[..6].as_bstr(), header.as_bstr(), "{}", spec);
+        if let BundleSpec::V1None = spec {
+            // There is no bundle2 framing around the changegroup.
+            assert_eq!(buf[6..].as_bstr(), changegroup.as_bstr());
+        }
+
+        let mut reader = BundleReader::new(&buf[..]).unwrap();
+        let part = reader.next_part().unwrap().unwrap();
+        assert_eq!(&*part.part_type, "changegroup");
+        let chunks = RevChunkIter::new(1, part)
+            .map(|chunk| {
+                (
+                    chunk.node(),
+                    chunk.parent1(),
+                    chunk.delta_node(),
+                    chunk
+                        .iter_diff()
+                        .map(|diff| (diff.start(), diff.end(), diff.data().to_vec()))
+                        .collect_vec(),
+                )
+            })
+            .collect_vec();
+        assert_eq!(
+            chunks,
+            vec![
+                (
+                    node(1),
+                    HgObjectId::NULL,
+                    HgObjectId::NULL,
+                    vec![(0, 0, b"foo\n".to_vec())]
+                ),
+                (node(2), node(1), node(1), vec![(0, 0, b"bar\n".to_vec())]),
+            ],
+            "{}",
+            spec
+        );
+    }
+}
+
 pub struct BundleConnection<R: Read> {
     reader: R,
     buf: Vec<u8>,
@@ -909,13 +1139,10 @@ impl<R: Read> BundleConnection<R> {
                 raw_changeset.extend_from_slice(&reference_cs[last_end..]);
                 let raw_changeset = RawHgChangeset::from(raw_changeset);
                 let changeset = raw_changeset.parse().unwrap();
-                let branch = changeset
-                    .extra()
-                    .and_then(|e| e.get(b"branch"))
-                    .unwrap_or(b"default")
-                    .as_bstr();
-
-                changesets.add(node, &parents, branch);
+                // Parents may be outside the bundle.
+                changesets
+                    .add(node, &parents, changeset.branch().as_bstr())
+                    .ok();
                 raw_changesets.insert(node, raw_changeset);
             }
             break;
@@ -1017,7 +1244,6 @@ fn write_chunk<T: core::ops::Deref<Target = [u8]>>(
     parent2: HgObjectId,
     changeset: HgChangesetId,
     previous: &mut Option<(HgObjectId, T)>,
-    always_previous: bool,
     mut f: impl FnMut(HgObjectId) -> T,
 ) -> io::Result<()> {
     let raw_object = f(node);
@@ -1034,14 +1260,7 @@ fn write_chunk<T: core::ops::Deref<Target = [u8]>>(
             .into_iter()
             .filter(|p| !p.is_null())
             .dedup()
-            .map(|p| {
-                if let (true, Some(p)) = (always_previous, previous_node) {
-                    let previous = raw_previous.as_ref().unwrap();
-                    (Some(p), create_chunk_data(previous, &raw_object))
-                } else {
-                    (Some(p), create_chunk_data(&f(p), &raw_object))
-                }
-            })
+            .map(|p| (Some(p), create_chunk_data(&f(p), &raw_object)))
             .collect_vec();
         if chunk_data.is_empty() {
             chunk_data.push((
@@ -1072,7 +1291,7 @@ pub fn create_bundle(
     changesets: impl Iterator<Item = [HgChangesetId; 3]>,
     bundlespec: BundleSpec,
     version: u8,
-    output: &File,
+    output: impl Write,
     replycaps: bool,
 ) -> ChangesetHeads {
     let mut part_id = 0;
@@ -1091,28 +1310,52 @@ pub fn create_bundle(
     let info = BundlePartInfo::new(part_id, "changegroup")
         .set_param("version", &format!("{:02}", version));
     let mut bundle_part_writer = bundle_writer.new_part(info).unwrap();
-    let mut previous = None;
+    let mut previous: Option<(HgChangesetId, RawHgChangeset)> = None;
     let mut manifests = IndexMap::new();
+    let read_changeset = |node: HgChangesetId| {
+        RawHgChangeset::read(store, node.to_git(store).unwrap())
+            .unwrap_or_else(|e| die!("Cannot read changeset {}: {}", node, e))
+    };
 
     for [node, parent1, parent2] in changesets.progress(|n| format!("Bundling {n} changesets")) {
         // TODO: add branch.
-        changeset_heads.add(node, &[parent1, parent2], b"".as_bstr());
-
-        write_chunk(
-            &mut bundle_part_writer,
-            version,
-            node.into(),
-            parent1.into(),
-            parent2.into(),
-            node,
-            &mut previous,
-            true,
-            |node| {
-                let node = HgChangesetId::from_unchecked(node);
-                RawHgChangeset::read(store, node.to_git(store).unwrap()).unwrap()
-            },
-        )
-        .unwrap();
+        // Parents are not part of the bundle.
+        changeset_heads
+            .add(node, &[parent1, parent2], b"".as_bstr())
+            .ok();
+
+        let raw_changeset = read_changeset(node);
+        // Changesets are deltas against the previous one. The first one is
+        // a delta against its first parent for version 1 changegroups, and
+        // against whichever parent gives the smallest delta for version 2.
+        let bases = match previous.take() {
+            Some(previous) => vec![previous],
+            None if version == 1 => [parent1]
+                .into_iter()
+                .filter(|p| !p.is_null())
+                .map(|p| (p, read_changeset(p)))
+                .collect_vec(),
+            None => [parent1, parent2]
+                .into_iter()
+                .filter(|p| !p.is_null())
+                .dedup()
+                .map(|p| (p, read_changeset(p)))
+                .collect_vec(),
+        };
+        let parents = [parent1, parent2];
+        let chunk = if bases.is_empty() {
+            raw_changeset.to_bundle_chunk(version, node, parents, None)
+        } else {
+            bases
+                .iter()
+                .map(|(base, raw_base)| {
+                    raw_changeset.to_bundle_chunk(version, node, parents, Some((*base, raw_base)))
+                })
+                .min_by_key(|chunk| chunk.len())
+                .unwrap()
+        };
+        bundle_part_writer.write_all(&chunk).unwrap();
+        previous = Some((node, raw_changeset));
         // We could derive the manifest parents from the parent changesets, but there
         // are cases where they are actually the opposites of the parent manifests,
         // so we have to go off the manifest dag.
@@ -1147,6 +1390,70 @@ pub fn create_bundle(
     changeset_heads
 }
 
+/// Writes a bundle with a changegroup containing the changesets that are
+/// ancestors of `heads` but not of `common`, followed by the manifests and
+/// files they reference. This is the inverse of `store_changegroup`.
+///
+/// Changesets are emitted parents first, so that every delta base comes
+/// before the revisions depending on it. An empty range produces a bundle
+/// with an empty changegroup.
+pub fn export_changegroup(
+    store: &Store,
+    heads: &[HgChangesetId],
+    common: &[HgChangesetId],
+    spec: BundleSpec,
+    out: impl Write,
+) -> Result<ChangesetHeads, String> {
+    let to_git = |ids: &[HgChangesetId]| {
+        HgChangesetId::to_git_batch(ids, store)
+            .into_iter()
+            .zip(ids)
+            .map(|(cid, cs)| cid.ok_or_else(|| format!("Unknown changeset id: {}", cs)))
+            .collect::<Result<Vec<_>, _>>()
+    };
+    let mut args = vec![
+        "--topo-order".to_string(),
+        "--full-history".to_string(),
+        "--reverse".to_string(),
+    ];
+    for head in to_git(heads)? {
+        args.push(head.to_string());
+    }
+    for cs in to_git(common)? {
+        args.push(format!("^{}", cs));
+    }
+    let to_hg = |cid: CommitId| {
+        GitChangesetId::from_unchecked(cid)
+            .to_hg(store)
+            .ok_or_else(|| format!("Not a mercurial changeset: {}", cid))
+    };
+    let mut changesets = Vec::new();
+    // Without heads, the range is empty, and there's no need to ask git.
+    if !heads.is_empty() {
+        for cid in rev_list(args) {
+            let commit = RawCommit::read(cid).unwrap();
+            let commit = commit.parse().unwrap();
+            let mut parents = [HgChangesetId::NULL; 2];
+            if commit.parents().len() > parents.len() {
+                return Err(format!("Not a mercurial changeset: {}", cid));
+            }
+            for (parent, cid) in parents.iter_mut().zip(commit.parents()) {
+                *parent = to_hg(*cid)?;
+            }
+            changesets.push([to_hg(cid)?, parents[0], parents[1]]);
+        }
+    }
+    let version = spec.changegroup_version();
+    Ok(create_bundle(
+        store,
+        changesets.into_iter(),
+        spec,
+        version,
+        out,
+        false,
+    ))
+}
+
 fn bundle_manifest<const CHUNK_SIZE: usize>(
     store: &Store,
     bundle_part_writer: &mut BundlePartWriter<CHUNK_SIZE>,
@@ -1172,7 +1479,6 @@ fn bundle_manifest<const CHUNK_SIZE: usize>(
             parent2.into(),
             changeset,
             &mut previous,
-            false,
             |node| {
                 let node = HgManifestId::from_unchecked(node);
                 RawHgManifest::read(node.to_git(store).unwrap()).unwrap()
@@ -1180,10 +1486,13 @@ fn bundle_manifest<const CHUNK_SIZE: usize>(
         )
         .unwrap();
         let git_node = node.to_git(store).unwrap();
-        let git_parents = [parent1, parent2]
+        let parents = [parent1, parent2]
             .into_iter()
             .filter(|p| !p.is_null())
-            .map(|p| (p.to_git(store).unwrap().into()))
+            .collect_vec();
+        let git_parents = HgManifestId::to_git_batch(&parents, store)
+            .into_iter()
+            .map(|p| p.unwrap().into())
             .collect_vec();
         for (path, (hg_file, hg_fileparents)) in
             get_changes(git_node.into(), &git_parents, false).map(WithPath::unzip)
@@ -1243,7 +1552,7 @@ fn bundle_files<const CHUNK_SIZE: usize>(
             // On merges, a file with copy metadata has either not parent, or only one.
             // In that latter case, the parent is always set as second parent.
             // On non-merges, a file with copy metadata doesn't have a parent.
-            if data.starts_with(b"\x01\n") {
+            if data.metadata().is_some() {
                 if !parent1.is_null() && !parent2.is_null() {
                     die!("Trying to create an invalid file. Please open an issue with details.");
                 }
@@ -1259,7 +1568,6 @@ fn bundle_files<const CHUNK_SIZE: usize>(
                 parent2.into(),
                 changeset,
                 &mut previous,
-                false,
                 |oid| RawHgFile::read_hg(store, HgFileId::from_unchecked(oid)).unwrap(),
             )
             .unwrap();
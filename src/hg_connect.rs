@@ -4,9 +4,9 @@
 
 use std::borrow::Cow;
 use std::collections::{HashMap, HashSet};
+use std::fmt;
 use std::fs::File;
-use std::io::{stderr, BufReader, Read, Write};
-use std::str::FromStr;
+use std::io::{self, stderr, BufReader, Read, Write};
 
 use bstr::{BStr, ByteSlice};
 use either::Either;
@@ -19,12 +19,15 @@ use url::Url;
 use crate::cinnabar::GitChangesetId;
 use crate::git::{CommitId, GitObjectId};
 use crate::hg::HgChangesetId;
-use crate::hg_bundle::{BundleReader, BundleSpec};
+use crate::hg_bundle::{BundlePartInfo, BundleReader, BundleSpec};
 use crate::hg_connect_http::get_http_connection;
 use crate::hg_connect_stdio::get_stdio_connection;
 use crate::libgit::{die, rev_list, RawCommit};
 use crate::oid::ObjectId;
-use crate::store::{has_metadata, merge_metadata, store_changegroup, Dag, Store, Traversal};
+use crate::store::{
+    check_changegroup, has_metadata, merge_metadata, store_changegroup, ChangegroupError, Dag,
+    Store, Traversal,
+};
 use crate::util::{FromBytes, ImmutBString, OsStrExt, PrefixWriter, SliceExt, ToBoxed};
 use crate::{check_enabled, get_config_remote, graft_config_enabled, Checks};
 
@@ -580,12 +583,28 @@ fn test_encode_decode_caps() {
     );
 }
 
+pub enum StoreBundleError {
+    /// An error message sent by the remote.
+    Remote(ImmutBString),
+    /// The changegroup received from the remote could not be imported.
+    Changegroup(ChangegroupError),
+}
+
+impl fmt::Display for StoreBundleError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StoreBundleError::Remote(e) => write!(f, "{}", e.as_bstr()),
+            StoreBundleError::Changegroup(e) => write!(f, "{}", e),
+        }
+    }
+}
+
 pub fn get_store_bundle(
     store: &Store,
     conn: &mut dyn HgRepo,
     heads: &[HgChangesetId],
     common: &[HgChangesetId],
-) -> Result<(), ImmutBString> {
+) -> Result<(), StoreBundleError> {
     let bundle2caps = if check_enabled(Checks::NO_BUNDLE2) {
         None
     } else {
@@ -597,23 +616,41 @@ pub fn get_store_bundle(
             )
         })
     };
-    conn.getbundle(heads, common, bundle2caps.as_deref())
-        .and_then(|r| {
-            let mut bundle = BundleReader::new(r).unwrap();
-            while let Some(part) = bundle.next_part().unwrap() {
-                if &*part.part_type == "changegroup" {
-                    let version = part
-                        .get_param("version")
-                        .map_or(1, |v| u8::from_str(v).unwrap());
-                    store_changegroup(store, BufReader::new(part), version);
-                } else if &*part.part_type == "stream2" {
-                    return Err(b"Stream bundles are not supported."
-                        .to_vec()
-                        .into_boxed_slice());
-                }
-            }
-            Ok(())
-        })
+    let r = conn
+        .getbundle(heads, common, bundle2caps.as_deref())
+        .map_err(StoreBundleError::Remote)?;
+    let mut bundle = BundleReader::new(r).unwrap();
+    while let Some(part) = bundle.next_part().unwrap() {
+        if &*part.part_type == "changegroup" {
+            let info = BundlePartInfo::clone(&part);
+            store_changegroup(store, BufReader::new(part), &info)
+                .map_err(StoreBundleError::Changegroup)?;
+        } else if &*part.part_type == "stream2" {
+            return Err(StoreBundleError::Remote(
+                b"Stream bundles are not supported.".to_boxed(),
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Like `get_store_bundle`, but only checks that the changegroups in the
+/// bundle can be imported, without storing anything.
+pub fn get_check_bundle(store: &Store, conn: &mut dyn HgRepo) -> Result<(), String> {
+    let r = conn
+        .getbundle(&[], &[], None)
+        .map_err(|e| e.as_bstr().to_string())?;
+    let read_error = |e: io::Error| format!("Cannot read bundle: {}", e);
+    let mut bundle = BundleReader::new(r).map_err(read_error)?;
+    while let Some(part) = bundle.next_part().map_err(read_error)? {
+        if &*part.part_type == "changegroup" {
+            let info = BundlePartInfo::clone(&part);
+            check_changegroup(store, BufReader::new(part), &info)?;
+        } else if &*part.part_type == "stream2" {
+            return Err("Stream bundles are not supported.".to_string());
+        }
+    }
+    Ok(())
 }
 
 fn take_sample<R: rand::Rng + ?Sized, T, const SIZE: usize>(
@@ -819,11 +856,14 @@ pub fn get_bundle(
         common = find_common(store, conn, known_branch_heads(store));
     }
 
-    get_store_bundle(store, conn, &heads, &common).map_err(|e| {
-        let stderr = stderr();
-        let mut writer = PrefixWriter::new("remote: ", stderr.lock());
-        writer.write_all(&e).unwrap();
-        "".to_string()
+    get_store_bundle(store, conn, &heads, &common).map_err(|e| match e {
+        StoreBundleError::Remote(e) => {
+            let stderr = stderr();
+            let mut writer = PrefixWriter::new("remote: ", stderr.lock());
+            writer.write_all(&e).unwrap();
+            "".to_string()
+        }
+        e @ StoreBundleError::Changegroup(_) => e.to_string(),
     })
 }
 
@@ -884,7 +924,7 @@ fn get_initial_bundle(
                 Ok(()) => {
                     return Ok(true);
                 }
-                Err(e) => {
+                Err(StoreBundleError::Remote(e)) => {
                     let stderr = stderr();
                     let mut writer = PrefixWriter::new("remote: ", stderr.lock());
                     writer.write_all(&e).unwrap();
@@ -892,6 +932,11 @@ fn get_initial_bundle(
                         return Err("clonebundles failed".to_string());
                     }
                 }
+                // Part of the clone bundle may already have been imported,
+                // so we can't fall back to a normal clone.
+                Err(e @ StoreBundleError::Changegroup(_)) => {
+                    return Err(e.to_string());
+                }
             };
         }
     }
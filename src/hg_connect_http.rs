@@ -23,9 +23,9 @@ use curl_sys::{
     curl_easy_getinfo, curl_easy_setopt, curl_slist_append, curl_slist_free_all, CURL,
     CURLINFO_CONTENT_TYPE, CURLINFO_EFFECTIVE_URL, CURLINFO_REDIRECT_COUNT, CURLINFO_RESPONSE_CODE,
     CURLOPT_ACCEPT_ENCODING, CURLOPT_CAINFO, CURLOPT_FAILONERROR, CURLOPT_FILE,
-    CURLOPT_FOLLOWLOCATION, CURLOPT_HTTPGET, CURLOPT_HTTPHEADER, CURLOPT_NOBODY, CURLOPT_POST,
-    CURLOPT_POSTFIELDSIZE_LARGE, CURLOPT_READDATA, CURLOPT_READFUNCTION, CURLOPT_URL,
-    CURLOPT_USERAGENT, CURLOPT_WRITEFUNCTION,
+    CURLOPT_FOLLOWLOCATION, CURLOPT_HEADERDATA, CURLOPT_HEADERFUNCTION, CURLOPT_HTTPGET,
+    CURLOPT_HTTPHEADER, CURLOPT_NOBODY, CURLOPT_POST, CURLOPT_POSTFIELDSIZE_LARGE,
+    CURLOPT_READDATA, CURLOPT_READFUNCTION, CURLOPT_URL, CURLOPT_USERAGENT, CURLOPT_WRITEFUNCTION,
 };
 use either::Either;
 use flate2::read::ZlibDecoder;
@@ -280,6 +280,7 @@ struct HttpResponseInfo {
     http_status: usize,
     redirected_to: Option<Url>,
     content_type: Option<String>,
+    headers: Vec<(String, String)>,
 }
 
 #[derive(Derivative)]
@@ -300,6 +301,7 @@ struct HttpThreadData {
     sender: Sender<HttpRequestChannelData>,
     curl: *mut CURL,
     first: bool,
+    headers: Vec<(String, String)>,
 }
 
 impl HttpRequest {
@@ -318,6 +320,18 @@ impl HttpRequest {
         self.follow_redirects = enable;
     }
 
+    /// Requests the response body starting at the given offset, provided
+    /// the resource still matches the given validator (an entity tag or a
+    /// last modification date from a previous response). Otherwise, the
+    /// server sends the full response body. The response body is requested
+    /// without content encoding, so that the offset matches what was
+    /// received from a previous, unencoded, request.
+    pub fn range_from(&mut self, offset: u64, validator: &str) {
+        self.header("Range", &format!("bytes={}-", offset));
+        self.header("If-Range", validator);
+        self.header("Accept-Encoding", "identity");
+    }
+
     fn header(&mut self, name: &str, value: &str) {
         self.headers.push((name.to_string(), value.to_string()));
     }
@@ -350,8 +364,15 @@ impl HttpRequest {
                     sender,
                     curl: slot.curl,
                     first: true,
+                    headers: Vec::new(),
                 };
                 curl_easy_setopt(slot.curl, CURLOPT_FILE, &mut data);
+                curl_easy_setopt(slot.curl, CURLOPT_HEADERDATA, &mut data);
+                curl_easy_setopt(
+                    slot.curl,
+                    CURLOPT_HEADERFUNCTION,
+                    http_request_header as *const c_void,
+                );
                 curl_easy_setopt(
                     slot.curl,
                     CURLOPT_WRITEFUNCTION,
@@ -433,6 +454,9 @@ impl HttpRequest {
                 let mut results = slot_results::new();
                 let result = run_one_slot(slot, &mut results);
                 curl_slist_free_all(headers);
+                // Don't leave a dangling pointer to `data` in the slot.
+                curl_easy_setopt(slot.curl, CURLOPT_HEADERFUNCTION, ptr::null::<c_void>());
+                curl_easy_setopt(slot.curl, CURLOPT_HEADERDATA, ptr::null::<c_void>());
                 http_send_info(&mut data);
                 if result == HTTP_OK {
                     Ok(())
@@ -530,6 +554,23 @@ impl HttpResponse {
     fn redirected_to(&self) -> Option<&Url> {
         self.info.redirected_to.as_ref()
     }
+
+    /// Whether the server honored the range requested with
+    /// `HttpRequest::range_from`.
+    pub fn is_partial(&self) -> bool {
+        self.info.http_status == 206
+    }
+
+    /// Returns the value of the given response header, if any. When
+    /// redirects were followed, only the headers of the last response are
+    /// available.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.info
+            .headers
+            .iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, v)| &v[..])
+    }
 }
 
 fn http_send_info(data: &mut HttpThreadData) {
@@ -575,12 +616,31 @@ fn http_send_info(data: &mut HttpThreadData) {
                     http_status: http_status as usize,
                     redirected_to,
                     content_type,
+                    headers: mem::take(&mut data.headers),
                 }))
                 .unwrap();
         }
     }
 }
 
+unsafe extern "C" fn http_request_header(
+    ptr: *const c_char,
+    size: usize,
+    nmemb: usize,
+    data: *mut c_void,
+) -> usize {
+    let data = (data as *mut HttpThreadData).as_mut().unwrap();
+    let line = std::slice::from_raw_parts(ptr as *const u8, size.checked_mul(nmemb).unwrap());
+    if line.starts_with(b"HTTP/") {
+        // Status line of a new response, e.g. after a redirect.
+        data.headers.clear();
+    } else if let Some((name, value)) = line.to_str().ok().and_then(|l| l.split_once(':')) {
+        data.headers
+            .push((name.trim().to_string(), value.trim().to_string()));
+    }
+    nmemb
+}
+
 unsafe extern "C" fn http_request_execute(
     ptr: *const c_char,
     size: usize,
@@ -3,6 +3,7 @@
  * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
 
 use std::ffi::{c_void, CStr, CString, OsStr, OsString};
+use std::io;
 use std::os::raw::{c_char, c_int, c_long, c_uint, c_ulong, c_ushort};
 use std::ptr;
 use std::str::FromStr;
@@ -353,6 +354,62 @@ impl RawBlob {
         BlobId::from_raw_bytes_array(hex!("e69de29bb2d1d6434b8b29ae775ad8c2e48c5391"));
 }
 
+#[allow(non_camel_case_types)]
+#[repr(transparent)]
+pub struct git_istream(c_void);
+
+extern "C" {
+    fn open_istream(
+        r: *mut repository,
+        oid: *const object_id,
+        typ: *mut object_type,
+        size: *mut c_ulong,
+        filter: *const c_void,
+    ) -> *mut git_istream;
+
+    fn read_istream(st: *mut git_istream, buf: *mut c_void, len: usize) -> isize;
+
+    fn close_istream(st: *mut git_istream) -> c_int;
+}
+
+/// Reader for the contents of a blob, without loading it entirely in memory.
+/// Large blobs stored loose or undeltified in packs are streamed from disk.
+pub struct BlobReader(*mut git_istream);
+
+impl io::Read for BlobReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let ret = unsafe { read_istream(self.0, buf.as_mut_ptr() as *mut _, buf.len()) };
+        usize::try_from(ret)
+            .map_err(|_| io::Error::new(io::ErrorKind::Other, "read_istream failed"))
+    }
+}
+
+impl Drop for BlobReader {
+    fn drop(&mut self) {
+        unsafe {
+            close_istream(self.0);
+        }
+    }
+}
+
+impl RawBlob {
+    pub fn stream(oid: BlobId) -> Option<BlobReader> {
+        let mut t = object_type::OBJ_NONE;
+        let mut len: c_ulong = 0;
+        let stream = unsafe {
+            open_istream(
+                the_repository,
+                &GitObjectId::from(oid).into(),
+                &mut t,
+                &mut len,
+                ptr::null(),
+            )
+        };
+        let reader = (!stream.is_null()).then_some(BlobReader(stream))?;
+        matches!(t, object_type::OBJ_BLOB).then_some(reader)
+    }
+}
+
 impl RawTree {
     pub const EMPTY_OID: TreeId =
         TreeId::from_raw_bytes_array(hex!("4b825dc642cb6eb9a060e54bf8d69288fbee4904"));
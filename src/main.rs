@@ -83,7 +83,7 @@ use std::path::{Path, PathBuf};
 use std::process::Command;
 use std::str::{self, from_utf8, FromStr};
 use std::sync::Mutex;
-use std::time::Instant;
+use std::time::{Instant, SystemTime};
 use std::{cmp, fmt};
 
 use bitflags::bitflags;
@@ -100,15 +100,18 @@ use git::{BlobId, CommitId, GitObjectId, TreeIsh};
 use git_version::git_version;
 use graft::{graft_finish, grafted, init_graft};
 use hg::{HgChangesetId, HgFileId, HgManifestId, ManifestEntry};
-use hg_bundle::{create_bundle, create_chunk_data, BundleSpec, RevChunkIter};
-use hg_connect::{get_bundle, get_clonebundle_url, get_connection, get_store_bundle, HgRepo};
+use hg_bundle::{create_bundle, create_chunk_data, export_changegroup, BundleSpec, RevChunkIter};
+use hg_connect::{
+    get_bundle, get_check_bundle, get_clonebundle_url, get_connection, get_store_bundle, HgRepo,
+};
 use itertools::EitherOrBoth::{Both, Left, Right};
 use itertools::{EitherOrBoth, Itertools};
 use libgit::{
     commit, config_get_value, die, diff_tree_with_copies, for_each_ref_in, for_each_remote,
-    get_oid_committish, get_unique_abbrev, lookup_commit, lookup_replace_commit, object_id,
-    reachable_subset, remote, repository, resolve_ref, rev_list, rev_list_with_boundaries,
-    the_repository, DiffTreeItem, MaybeBoundary, RawBlob, RawCommit, RawTree, RefTransaction,
+    get_oid_blob, get_oid_committish, get_unique_abbrev, lookup_commit, lookup_replace_commit,
+    object_id, reachable_subset, remote, repository, resolve_ref, rev_list,
+    rev_list_with_boundaries, the_repository, DiffTreeItem, MaybeBoundary, RawBlob, RawCommit,
+    RawTree, RefTransaction,
 };
 use logging::{LoggingReader, LoggingWriter};
 use oid::{Abbrev, ObjectId};
@@ -117,11 +120,18 @@ use percent_encoding::{percent_decode, percent_encode, AsciiSet, CONTROLS};
 use progress::Progress;
 use sha1::{Digest, Sha1};
 use store::{
-    check_file, check_manifest, create_changeset, do_check_files, do_store_metadata,
-    ensure_store_init, has_metadata, raw_commit_for_changeset, store_git_blob, store_manifest,
-    ChangesetHeads, GeneratedGitChangesetMetadata, RawGitChangesetMetadata, RawHgChangeset,
-    RawHgFile, RawHgManifest, SetWhat, Store, BROKEN_REF, CHECKED_REF, METADATA_REF, NOTES_REF,
-    REFS_PREFIX, REPLACE_REFS_PREFIX,
+    all_manifests_topological, changeset_manifest_entries, changeset_patch_ops,
+    check_all_changeset_metadata, check_file, check_file_metadata_flags, check_manifest,
+    check_manifest_consistency, check_parent_order, check_replace_refs, check_stored_bundle,
+    create_changeset, do_check_files, do_store_metadata, ensure_store_init, escape_extra,
+    export_tags_listkeys, has_metadata, merge_metadata, metadata_updated_at,
+    raw_commit_for_changeset, resolve_git_changeset_prefix, store_changeset_heads_cache,
+    store_git_blob, store_manifest, store_metadata_updated_at, stored_bundle_reader, tags_to_json,
+    BundleCheckResult, ChangesetHeads, FileMetadataCheck, GeneratedGitChangesetMetadata,
+    ManifestReader, ParentOrder, Phase, RawGitChangesetMetadata, RawHgChangeset, RawHgFile,
+    RawHgManifest, SetWhat, Store, TagsCache, BROKEN_REF, CHANGESET_HEADS_REF, CHECKED_REF,
+    FILES_CHECKED_REF, METADATA_REF, METADATA_UPDATED_REF, NOTES_REF, REFS_PREFIX,
+    REPLACE_REFS_PREFIX,
 };
 use tree_util::{diff_by_path, RecurseTree};
 use url::Url;
@@ -216,6 +226,11 @@ fn do_done_and_check(store: &mut Store, args: &[&[u8]]) -> bool {
             return false;
         }
         let new_metadata = do_store_metadata(store);
+        let heads_cache = (!check_enabled(Checks::NO_HEADS_CACHE))
+            .then(|| store_changeset_heads_cache(store, new_metadata));
+        let updated_at = (new_metadata != store.metadata_cid
+            || metadata_updated_at(new_metadata).is_none())
+        .then(|| store_metadata_updated_at(new_metadata, SystemTime::now()));
         do_cleanup(0);
         set_metadata_to(
             Some(new_metadata),
@@ -230,7 +245,21 @@ fn do_done_and_check(store: &mut Store, args: &[&[u8]]) -> bool {
                 .unwrap();
             transaction.commit().unwrap();
         }
-        *store = Store::new(Some(new_metadata));
+        if let Some(heads_cache) = heads_cache {
+            let mut transaction = RefTransaction::new().unwrap();
+            transaction
+                .update(CHANGESET_HEADS_REF, heads_cache, None, "update")
+                .unwrap();
+            transaction.commit().unwrap();
+        }
+        if let Some(updated_at) = updated_at {
+            let mut transaction = RefTransaction::new().unwrap();
+            transaction
+                .update(METADATA_UPDATED_REF, updated_at, None, "update")
+                .unwrap();
+            transaction.commit().unwrap();
+        }
+        store.reload(new_metadata);
     }
     do_check_files(store)
 }
@@ -260,10 +289,27 @@ fn do_one_hg2git(store: &Store, sha1: Abbrev<HgChangesetId>) -> String {
 fn do_one_git2hg(store: &Store, committish: OsString) -> String {
     let note = get_oid_committish(committish.as_bytes())
         .map(lookup_replace_commit)
-        .and_then(|oid| GitChangesetId::from_unchecked(oid).to_hg(store));
+        .and_then(|oid| GitChangesetId::from_unchecked(oid).to_hg(store))
+        .or_else(|| {
+            // Git can't resolve prefixes matching several commits, but
+            // only one of them may be a changeset.
+            resolve_git_changeset_prefix(store, committish.as_bytes())
+                .unwrap_or_else(|e| {
+                    error!(target: "root", "{}", e);
+                    None
+                })
+                .and_then(|cid| cid.to_hg(store))
+        });
     format!("{}", note.unwrap_or(HgChangesetId::NULL))
 }
 
+fn do_one_git2hg_file(files: &HashMap<GitObjectId, Vec<HgFileId>>, blobish: OsString) -> String {
+    match get_oid_blob(blobish.as_bytes()).and_then(|blob| files.get(&GitObjectId::from(blob))) {
+        Some(files) => files.iter().join(" "),
+        None => format!("{}", HgFileId::NULL),
+    }
+}
+
 fn do_conversion<T, I: Iterator<Item = T>, F: FnMut(T) -> Result<String, String>, W: Write>(
     abbrev: Option<usize>,
     input: I,
@@ -315,7 +361,203 @@ where
     Ok(())
 }
 
-fn do_data_changeset(store: &Store, rev: Abbrev<HgChangesetId>) -> Result<(), String> {
+fn do_tags(store: &Store, json: bool, listkeys: bool, names: Vec<OsString>) -> Result<(), String> {
+    let tags = store.get_tags();
+    if listkeys {
+        let mut buf = Vec::new();
+        export_tags_listkeys(&tags, &mut buf).map_err(|e| e.to_string())?;
+        if !buf.is_empty() {
+            buf.push(b'\n');
+        }
+        return stdout().write_all(&buf).map_err(|e| e.to_string());
+    }
+    let tags = if names.is_empty() {
+        tags.iter_with_local().sorted().collect_vec()
+    } else {
+        names
+            .iter()
+            .map(|name| {
+                let name = name.as_bytes();
+                tags.get(name)
+                    .map(|node| (name, node, tags.is_local(name)))
+                    .ok_or_else(|| format!("Unknown tag: {}", name.as_bstr()))
+            })
+            .collect::<Result<Vec<_>, _>>()?
+    };
+    let out = stdout();
+    let mut out = BufWriter::new(out.lock());
+    if json {
+        let json = tags_to_json(tags, |node| node.to_git(store));
+        return writeln!(out, "{}", json).map_err(|e| e.to_string());
+    }
+    for (tag, node, local) in tags {
+        let cid = node.to_git(store).map_or(CommitId::NULL, CommitId::from);
+        let suffix = if local { " (local)" } else { "" };
+        writeln!(out, "{} {} {}{}", node, cid, tag.as_bstr(), suffix).map_err(|e| e.to_string())?;
+    }
+    Ok(())
+}
+
+fn do_branches(
+    store: &Store,
+    closed: bool,
+    tips: bool,
+    branch: Option<OsString>,
+) -> Result<(), String> {
+    if let Some(branch) = branch {
+        let out = stdout();
+        let mut out = BufWriter::new(out.lock());
+        for cs in store.changesets_on_branch(branch.as_bytes()) {
+            let cid = cs.to_git(store).map_or(CommitId::NULL, CommitId::from);
+            writeln!(out, "{} {}", cs, cid).map_err(|e| e.to_string())?;
+        }
+        return Ok(());
+    }
+    let heads = store.changeset_heads();
+    let closed_branches = heads.fully_closed_branches();
+    let branch_tips = tips.then(|| heads.branch_tips());
+    let out = stdout();
+    let mut out = BufWriter::new(out.lock());
+    for branch in heads
+        .branch_heads()
+        .map(|(_, branch)| branch)
+        .unique()
+        .sorted()
+    {
+        let is_closed = closed_branches.iter().any(|b| b.as_bstr() == branch);
+        if is_closed && !closed {
+            continue;
+        }
+        let suffix = if is_closed { " (closed)" } else { "" };
+        if let Some(tip) = branch_tips.as_ref().and_then(|tips| tips.get(branch)) {
+            write!(out, "{} ", tip).map_err(|e| e.to_string())?;
+        }
+        writeln!(out, "{}{}", branch, suffix).map_err(|e| e.to_string())?;
+    }
+    Ok(())
+}
+
+fn do_heads(store: &Store, closed: bool) -> Result<(), String> {
+    let heads = store.changeset_heads();
+    let open_heads = heads
+        .open_branch_heads()
+        .map(|(head, _)| *head)
+        .collect::<HashSet<_>>();
+    let out = stdout();
+    let mut out = BufWriter::new(out.lock());
+    for (head, branch) in heads.branch_heads() {
+        let is_closed = !open_heads.contains(head);
+        if is_closed && !closed {
+            continue;
+        }
+        let cid = head.to_git(store).map_or(CommitId::NULL, CommitId::from);
+        let suffix = if is_closed { " (closed)" } else { "" };
+        writeln!(out, "{} {} {}{}", head, cid, branch, suffix).map_err(|e| e.to_string())?;
+    }
+    Ok(())
+}
+
+// Builds the DAG of the given changesets and all their ancestors. Contrary to
+// the changeset heads from the store, which may only know about the heads,
+// it contains the whole history.
+fn changeset_history(
+    store: &Store,
+    changesets: &[HgChangesetId],
+) -> Result<ChangesetHeads, String> {
+    let mut history = ChangesetHeads::new();
+    if changesets.is_empty() {
+        return Ok(history);
+    }
+    let to_hg = |cid: CommitId| {
+        GitChangesetId::from_unchecked(cid)
+            .to_hg(store)
+            .ok_or_else(|| format!("Not a mercurial changeset: {}", cid))
+    };
+    let args = ["--topo-order", "--full-history", "--reverse"]
+        .into_iter()
+        .map(str::to_owned)
+        .chain(changesets.iter().map(|cs| cs.to_git_hex(store).unwrap()));
+    for cid in rev_list(args).progress(|n| format!("Reading {n} changesets")) {
+        let commit = RawCommit::read(cid).unwrap();
+        let commit = commit.parse().unwrap();
+        let parents = commit
+            .parents()
+            .iter()
+            .map(|p| to_hg(*p))
+            .collect::<Result<Vec<_>, _>>()?;
+        // Branches don't matter for the DAG, and parents always come first.
+        history.add(to_hg(cid)?, &parents, b"".as_bstr()).unwrap();
+    }
+    Ok(history)
+}
+
+fn do_stats(
+    store: &Store,
+    shared_trees: bool,
+    manifests: bool,
+    head: Vec<Abbrev<HgChangesetId>>,
+    common: Vec<Abbrev<HgChangesetId>>,
+    changeset: Option<Abbrev<HgChangesetId>>,
+) -> Result<(), String> {
+    let out = stdout();
+    let mut out = BufWriter::new(out.lock());
+    if let Some(changeset) = changeset {
+        let changeset = resolve_changeset(store, &changeset)?;
+        let heads = store.changeset_heads().heads().copied().collect_vec();
+        let history = changeset_history(store, &heads)?;
+        let rev = history
+            .rev_number(&changeset)
+            .ok_or_else(|| format!("Changeset {} is not in the history", changeset))?;
+        // The changeset resolved, so it has a git commit.
+        let commit = changeset.to_git_hex(store).unwrap();
+        writeln!(out, "commit: {}", commit)
+            .and_then(|_| writeln!(out, "rev: {}", rev))
+            .and_then(|_| writeln!(out, "descendants: {}", history.descendant_count(&changeset)))
+            .map_err(|e| e.to_string())?;
+    } else if !head.is_empty() {
+        let head = head
+            .iter()
+            .map(|cs| resolve_changeset(store, cs))
+            .collect::<Result<Vec<_>, _>>()?;
+        let common = common
+            .iter()
+            .map(|cs| resolve_changeset(store, cs))
+            .collect::<Result<Vec<_>, _>>()?;
+        let history = changeset_history(store, &head.iter().chain(&common).copied().collect_vec())?;
+        writeln!(out, "changesets: {}", history.count_range(&common, &head))
+            .map_err(|e| e.to_string())?;
+    } else if manifests {
+        for manifest in all_manifests_topological(store) {
+            writeln!(out, "{}", manifest).map_err(|e| e.to_string())?;
+        }
+    } else if shared_trees {
+        for (tree, changesets) in store.changesets_by_tree() {
+            writeln!(out, "{} {}", tree, changesets.iter().join(" ")).map_err(|e| e.to_string())?;
+        }
+    } else {
+        writeln!(out, "changesets: {}", store.iter_changesets().count())
+            .and_then(|_| writeln!(out, "file revisions: {}", store.file_revision_count()))
+            .map_err(|e| e.to_string())?;
+    }
+    Ok(())
+}
+
+#[derive(Clone, Copy)]
+enum ChangesetData {
+    Raw,
+    Extra,
+    Phase,
+    Files,
+    Patch,
+    Manifest,
+    ManifestDelta,
+}
+
+fn do_data_changeset(
+    store: &Store,
+    rev: Abbrev<HgChangesetId>,
+    what: ChangesetData,
+) -> Result<(), String> {
     let commit_id = store
         .hg2git_mut()
         .get_note_abbrev(rev)
@@ -324,8 +566,85 @@ fn do_data_changeset(store: &Store, rev: Abbrev<HgChangesetId>) -> Result<(), St
         store,
         GitChangesetId::from_unchecked(CommitId::from_unchecked(commit_id)),
     )
-    .unwrap();
-    stdout().write_all(&changeset).map_err(|e| e.to_string())
+    .map_err(|e| format!("Cannot read changeset {}: {}", rev, e))?;
+    if let ChangesetData::Raw = what {
+        return stdout().write_all(&changeset).map_err(|e| e.to_string());
+    }
+    let changeset = changeset
+        .parse()
+        .ok_or_else(|| format!("Cannot parse changeset {}", rev))?;
+    let out = stdout();
+    let mut out = BufWriter::new(out.lock());
+    match what {
+        ChangesetData::Raw => unreachable!(),
+        ChangesetData::Extra => {
+            if let Some(extra) = changeset.extra() {
+                for (key, value) in extra.iter() {
+                    writeln!(
+                        out,
+                        "{}:{}",
+                        escape_extra(&key).as_bstr(),
+                        escape_extra(&value).as_bstr()
+                    )
+                    .map_err(|e| e.to_string())?;
+                }
+            }
+        }
+        ChangesetData::Phase => {
+            let phase = match changeset.extra().and_then(|e| e.phase_hint()) {
+                Some(Phase::Public) => "public",
+                Some(Phase::Draft) => "draft",
+                Some(Phase::Secret) => "secret",
+                None => "unknown",
+            };
+            writeln!(out, "{}", phase).map_err(|e| e.to_string())?;
+        }
+        ChangesetData::Files => {
+            let cid = GitChangesetId::from_unchecked(CommitId::from_unchecked(commit_id));
+            let changes = cid
+                .to_hg(store)
+                .and_then(|cs| changeset_file_changes(store, &cs))
+                .ok_or_else(|| format!("Cannot read the files changed in changeset {}", rev))?;
+            for (path, kind) in changes {
+                let kind = match kind {
+                    ChangeKind::Added => "A",
+                    ChangeKind::Modified => "M",
+                    ChangeKind::Deleted => "D",
+                };
+                writeln!(out, "{} {}", kind, path.as_bstr()).map_err(|e| e.to_string())?;
+            }
+        }
+        ChangesetData::Patch => {
+            let cid = GitChangesetId::from_unchecked(CommitId::from_unchecked(commit_id));
+            let ops = changeset_patch_ops(store, &cid)
+                .map_err(|e| format!("Cannot read the metadata of changeset {}: {}", rev, e))?;
+            for (start, end, data) in ops.unwrap_or_default() {
+                writeln!(out, "{} {} {:?}", start, end, data.as_bstr())
+                    .map_err(|e| e.to_string())?;
+            }
+        }
+        ChangesetData::Manifest => {
+            let cid = GitChangesetId::from_unchecked(CommitId::from_unchecked(commit_id));
+            let entries = cid
+                .to_hg(store)
+                .and_then(|cs| changeset_manifest_entries(store, &cs))
+                .ok_or_else(|| format!("Cannot read the manifest of changeset {}", rev))?;
+            std::io::copy(&mut ManifestReader::new(entries), &mut out)
+                .map_err(|e| e.to_string())?;
+        }
+        ChangesetData::ManifestDelta => {
+            let cid = GitChangesetId::from_unchecked(CommitId::from_unchecked(commit_id));
+            let delta = cid
+                .to_hg(store)
+                .and_then(|cs| changeset_manifest_delta(store, &cs))
+                .ok_or_else(|| format!("Cannot read the manifest of changeset {}", rev))?;
+            for entry in delta {
+                let (new, old) = entry.inner();
+                writeln!(out, "{} {} {}", new, old, entry.path()).map_err(|e| e.to_string())?;
+            }
+        }
+    }
+    Ok(())
 }
 
 fn do_data_manifest(store: &Store, rev: Abbrev<HgManifestId>) -> Result<(), String> {
@@ -333,11 +652,15 @@ fn do_data_manifest(store: &Store, rev: Abbrev<HgManifestId>) -> Result<(), Stri
         .hg2git_mut()
         .get_note_abbrev(rev)
         .ok_or_else(|| format!("Unknown manifest id: {}", rev))?;
-    let manifest = RawHgManifest::read(GitManifestId::from_unchecked(CommitId::from_unchecked(
-        commit_id,
-    )))
+    let mut manifest = RawHgManifest::read_streaming(GitManifestId::from_unchecked(
+        CommitId::from_unchecked(commit_id),
+    ))
     .unwrap();
-    stdout().write_all(&manifest).map_err(|e| e.to_string())
+    let out = stdout();
+    let mut out = BufWriter::new(out.lock());
+    std::io::copy(&mut manifest, &mut out)
+        .and_then(|_| out.flush())
+        .map_err(|e| e.to_string())
 }
 
 fn hg_url(url: impl AsRef<OsStr>) -> Option<Url> {
@@ -659,6 +982,16 @@ fn set_metadata_to(
             (Some(_), _) if r.as_bytes().starts_with(REPLACE_REFS_PREFIX.as_bytes()) => {
                 replace_refs.insert(r, oid);
             }
+            // The files-checked ref records the file notes that were checked,
+            // which remains valid whatever the metadata is set to.
+            (Some(_), _) if r == FILES_CHECKED_REF => {}
+            // The changeset heads cache and the metadata update time are
+            // only valid for the metadata they were recorded for.
+            (Some(new), _)
+                if (r == CHANGESET_HEADS_REF || r == METADATA_UPDATED_REF)
+                    && RawCommit::read(oid)
+                        .and_then(|c| c.parse().map(|c| c.parents() == [new]))
+                        .unwrap_or(false) => {}
             _ => {
                 transaction.delete(r, Some(oid), msg)?;
             }
@@ -1694,7 +2027,15 @@ fn do_setup() -> Result<(), String> {
     Ok(())
 }
 
-fn do_data_file(store: &Store, rev: Abbrev<HgFileId>) -> Result<(), String> {
+#[derive(Clone, Copy)]
+enum FileData {
+    Raw,
+    Content,
+    Metadata,
+    Copy,
+}
+
+fn do_data_file(store: &Store, rev: Abbrev<HgFileId>, what: FileData) -> Result<(), String> {
     let mut stdout = stdout();
     let blob_id = store
         .hg2git_mut()
@@ -1706,7 +2047,27 @@ fn do_data_file(store: &Store, rev: Abbrev<HgFileId>) -> Result<(), String> {
         .get_note_abbrev(rev)
         .map(|oid| GitFileMetadataId::from_unchecked(BlobId::from_unchecked(oid)));
     let file = RawHgFile::read(file_id, metadata_id).unwrap();
-    stdout.write_all(&file).map_err(|e| e.to_string())
+    let data = match what {
+        FileData::Raw => &file[..],
+        FileData::Content => file.content(),
+        FileData::Metadata | FileData::Copy => {
+            let metadata = file
+                .file_metadata()
+                .transpose()
+                .map_err(|e| format!("Invalid metadata for file {}: {}", rev, e))?;
+            if let FileData::Copy = what {
+                if let Some((path, copy_rev)) =
+                    metadata.and_then(|m| Some((m.copy_from()?, m.copy_rev()?)))
+                {
+                    writeln!(stdout, "{} {}", copy_rev, path.as_bstr())
+                        .map_err(|e| e.to_string())?;
+                }
+                return Ok(());
+            }
+            file.metadata().unwrap_or_default()
+        }
+    };
+    stdout.write_all(data).map_err(|e| e.to_string())
 }
 
 pub fn graft_config_enabled(remote: Option<&str>) -> Result<Option<bool>, String> {
@@ -1720,7 +2081,12 @@ pub fn graft_config_enabled(remote: Option<&str>) -> Result<Option<bool>, String
         .map_err(|e| format!("Invalid value for cinnabar.graft: {}", e.to_string_lossy()))
 }
 
-fn do_unbundle(store: &mut Store, clonebundle: bool, mut url: OsString) -> Result<(), String> {
+fn do_unbundle(
+    store: &mut Store,
+    clonebundle: bool,
+    check: bool,
+    mut url: OsString,
+) -> Result<(), String> {
     if !url.as_bytes().starts_with(b"hg:") {
         let mut new_url = OsString::from("hg::");
         new_url.push(url);
@@ -1730,7 +2096,7 @@ fn do_unbundle(store: &mut Store, clonebundle: bool, mut url: OsString) -> Resul
     if !["http", "https", "file"].contains(&url.scheme()) {
         Err(format!("{} urls are not supported.", url.scheme()))?;
     }
-    if graft_config_enabled(None)?.unwrap_or(false) {
+    if !check && graft_config_enabled(None)?.unwrap_or(false) {
         init_graft(store);
     }
     if clonebundle {
@@ -1743,19 +2109,45 @@ fn do_unbundle(store: &mut Store, clonebundle: bool, mut url: OsString) -> Resul
     }
     let mut conn = get_connection(&url).unwrap();
 
-    get_store_bundle(store, &mut *conn, &[], &[])
-        .map_err(|e| String::from_utf8_lossy(&e).into_owned())?;
+    if check {
+        return get_check_bundle(store, &mut *conn);
+    }
+    get_store_bundle(store, &mut *conn, &[], &[]).map_err(|e| e.to_string())?;
 
     do_done_and_check(store, &[])
         .then_some(())
         .ok_or_else(|| "Fatal error".to_string())
 }
 
+fn do_merge_metadata(store: &mut Store, url: OsString, branch: OsString) -> Result<(), String> {
+    // Reuse the url parsing for mercurial urls, which handles paths.
+    let mut new_url = OsString::from("hg::");
+    new_url.push(&url);
+    let git_url =
+        hg_url(&new_url).ok_or_else(|| format!("Invalid url: {}", url.to_string_lossy()))?;
+    if !merge_metadata(store, git_url, None, Some(branch.as_bytes())) {
+        return Err("Failed to merge cinnabar metadata".to_string());
+    }
+    do_done_and_check(store, &[])
+        .then_some(())
+        .ok_or_else(|| "Fatal error".to_string())
+}
+
+fn resolve_changeset(store: &Store, cs: &Abbrev<HgChangesetId>) -> Result<HgChangesetId, String> {
+    store
+        .hg2git_mut()
+        .get_note_abbrev(*cs)
+        .and_then(|cid| GitChangesetId::from_unchecked(CommitId::from_unchecked(cid)).to_hg(store))
+        .ok_or_else(|| format!("Unknown changeset id: {}", cs))
+}
+
 fn do_bundle(
     store: &Store,
     version: u8,
     bundlespec: Option<BundleSpec>,
     path: PathBuf,
+    heads: &[Abbrev<HgChangesetId>],
+    common: &[Abbrev<HgChangesetId>],
     mut revs: Vec<OsString>,
 ) -> Result<i32, String> {
     let bundlespec = bundlespec.unwrap_or(match version {
@@ -1763,31 +2155,46 @@ fn do_bundle(
         2 => BundleSpec::V2None,
         v => return Err(format!("Unknown version {v}")),
     });
-    let version = match bundlespec {
-        BundleSpec::ChangegroupV1
-        | BundleSpec::V1None
-        | BundleSpec::V1Gzip
-        | BundleSpec::V1Bzip => 1,
-        BundleSpec::V2None | BundleSpec::V2Gzip | BundleSpec::V2Bzip | BundleSpec::V2Zstd => 2,
+    let version = bundlespec.changegroup_version();
+    let result = if heads.is_empty() {
+        revs.extend([
+            "--topo-order".into(),
+            "--full-history".into(),
+            "--reverse".into(),
+        ]);
+        let commits = rev_list(revs).map(|c| {
+            let commit = RawCommit::read(c).unwrap();
+            let commit = commit.parse().unwrap();
+            (c, commit.parents().to_boxed())
+        });
+        let file = File::create(path).unwrap();
+        do_create_bundle(store, commits, bundlespec, version, &file, false).map(|_| 0)
+    } else {
+        let heads = heads
+            .iter()
+            .map(|cs| resolve_changeset(store, cs))
+            .collect::<Result<Vec<_>, _>>()?;
+        let common = common
+            .iter()
+            .map(|cs| resolve_changeset(store, cs))
+            .collect::<Result<Vec<_>, _>>()?;
+        let file = File::create(path).map_err(|e| e.to_string())?;
+        export_changegroup(store, &heads, &common, bundlespec, &file).map(|_| 0)
     };
-    revs.extend([
-        "--topo-order".into(),
-        "--full-history".into(),
-        "--reverse".into(),
-    ]);
-    let commits = rev_list(revs).map(|c| {
-        let commit = RawCommit::read(c).unwrap();
-        let commit = commit.parse().unwrap();
-        (c, commit.parents().to_boxed())
-    });
-    let file = File::create(path).unwrap();
-    let result = do_create_bundle(store, commits, bundlespec, version, &file, false).map(|_| 0);
     unsafe {
         do_cleanup(1);
     }
     result
 }
 
+fn do_stored_bundle(store: &Store, path: PathBuf) -> Result<i32, String> {
+    let mut bundle = stored_bundle_reader(store)
+        .ok_or_else(|| "No bundle is stored along the metadata".to_string())?;
+    let mut file = File::create(path).map_err(|e| e.to_string())?;
+    std::io::copy(&mut bundle, &mut file).map_err(|e| e.to_string())?;
+    Ok(0)
+}
+
 fn create_file(store: &Store, blobid: BlobId, parents: &[HgFileId]) -> HgFileId {
     let blob = RawBlob::read(blobid).unwrap();
     let mut hash = HgFileId::create();
@@ -2482,11 +2889,8 @@ fn do_fsck(
             continue;
         }
         let changeset = raw_changeset.parse().unwrap();
-        let changeset_branch = changeset
-            .extra()
-            .and_then(|e| e.get(b"branch"))
-            .unwrap_or(b"default")
-            .as_bstr();
+        let changeset_branch = changeset.branch();
+        let changeset_branch = changeset_branch.as_bstr();
         if branch != changeset_branch {
             report(format!(
                 "Inconsistent metadata:\n\
@@ -2595,6 +2999,12 @@ fn do_fsck(
         if unsafe { check_manifest(&object_id::from(git_mid)) } != 1 {
             report(format!("Sha1 mismatch for manifest {}", git_mid));
         }
+        if !check_manifest_consistency(&git_mid) {
+            report(format!(
+                "Manifest {} doesn't match its git tree",
+                hg_manifest_id
+            ));
+        }
         let files: Vec<(_, HgFileId)> = if let Some(previous) = previous {
             let a = GitManifestTree::read_treeish(GitManifestId::from_unchecked(previous)).unwrap();
             let b = GitManifestTree::read_treeish(GitManifestId::from_unchecked(mid)).unwrap();
@@ -2715,6 +3125,18 @@ fn do_fsck(
         return Ok(1);
     }
 
+    for problem in check_replace_refs() {
+        report(problem.to_string());
+    }
+
+    for cs in store.changeset_heads().check_reachability() {
+        report(format!("Changeset {} is not reachable from any head", cs));
+    }
+
+    if let BundleCheckResult::Invalid(name, e) = check_stored_bundle(&metadata_cid) {
+        report(format!("Invalid {} in changesets metadata: {}", name, e));
+    }
+
     check_replace(metadata_cid);
 
     if broken.get() {
@@ -2859,17 +3281,18 @@ fn do_fsck_full(
             }
         }
         seen_changesets.insert(changeset_id);
-        let raw_changeset =
-            if let Some(raw_changeset) = RawHgChangeset::from_metadata(store, &commit, &metadata) {
-                raw_changeset
-            } else {
+        let raw_changeset = match RawHgChangeset::from_metadata(store, &commit, &metadata) {
+            Ok(raw_changeset) => raw_changeset,
+            Err(e) => {
                 report(format!(
-                    "Failed to recreate changeset {} from git commit {}",
+                    "Failed to recreate changeset {} from git commit {}: {}",
                     metadata.changeset_id(),
-                    cid
+                    cid,
+                    e
                 ));
                 continue;
-            };
+            }
+        };
         let mut sha1 = Sha1::new();
         let hg_parents = commit
             .parents()
@@ -2894,6 +3317,20 @@ fn do_fsck_full(
             report(format!("Sha1 mismatch for changeset {}", changeset_id));
             continue;
         }
+        if hg_parents.len() == 2 {
+            match check_parent_order(store, &changeset_id) {
+                ParentOrder::Ok => {}
+                ParentOrder::Swapped => {
+                    report(format!("Swapped parents for changeset {}", changeset_id));
+                }
+                ParentOrder::Unknown => {
+                    report(format!(
+                        "Could not check parents order for changeset {}",
+                        changeset_id
+                    ));
+                }
+            }
+        }
 
         let changeset = raw_changeset.parse().unwrap();
 
@@ -2924,9 +3361,14 @@ fn do_fsck_full(
 
         let branch = metadata
             .extra()
-            .and_then(|e| e.get(b"branch"))
-            .unwrap_or(b"default");
-        changeset_heads.add(changeset_id, &hg_parents, branch.as_bstr());
+            .and_then(|e| e.get_escaped(b"branch"))
+            .unwrap_or(Cow::Borrowed(b"default"));
+        if let Err(e) = changeset_heads.add(changeset_id, &hg_parents, branch.as_bstr()) {
+            // Only a full fsck goes through all changesets, parents first.
+            if full_fsck {
+                report(e.to_string());
+            }
+        }
 
         let fresh_metadata =
             GeneratedGitChangesetMetadata::generate(store, &commit, changeset_id, &raw_changeset)
@@ -3045,6 +3487,24 @@ fn do_fsck_full(
                     ));
                 }
             }
+            let problem = match check_file_metadata_flags(store, &hg_file) {
+                Some(FileMetadataCheck::Consistent) => continue,
+                Some(FileMetadataCheck::MissingEnvelope) => {
+                    "content starts with \\x01\\n, but no metadata was stored".to_string()
+                }
+                Some(FileMetadataCheck::UnneededEnvelope) => {
+                    "empty metadata was stored, but content doesn't start with \\x01\\n".to_string()
+                }
+                Some(FileMetadataCheck::InvalidMetadata(e)) => e.to_string(),
+                None => "could not be read".to_string(),
+            };
+            report(format!(
+                "Inconsistent metadata for file {}\n\
+                 \x20 revision {}: {}",
+                path.as_bstr(),
+                hg_file,
+                problem
+            ));
         }
     }
 
@@ -3111,6 +3571,17 @@ fn do_fsck_full(
         }
     }
 
+    if full_fsck {
+        // Notes for commits that are not reachable from the metadata were
+        // not checked above, and can't be removed as dangling when they
+        // can't be parsed.
+        for cid in check_all_changeset_metadata(store) {
+            if !seen_git2hg.contains(&cid) {
+                report(format!("Cannot parse note for git commit: {}", cid));
+            }
+        }
+    }
+
     if full_fsck && !broken.get() {
         let mut dangling = Vec::new();
         store.hg2git_mut().for_each(|h, _| {
@@ -3152,6 +3623,10 @@ fn do_fsck_full(
         }
     }
 
+    if let BundleCheckResult::Invalid(name, e) = check_stored_bundle(&metadata_cid) {
+        report(format!("Invalid {} in changesets metadata: {}", name, e));
+    }
+
     check_replace(metadata_cid);
 
     if full_fsck {
@@ -3252,9 +3727,251 @@ fn get_changes(
     }
 }
 
-fn manifest_diff(a: CommitId, b: CommitId) -> impl Iterator<Item = WithPath<(HgFileId, HgFileId)>> {
+/// A file that differs between two manifests, as (new, old) file nodes.
+/// A null node stands for a file that doesn't exist on that side.
+type ManifestDiffEntry = WithPath<(HgFileId, HgFileId)>;
+
+fn changeset_manifest_tree(store: &Store, cid: GitChangesetId) -> Option<GitManifestTree> {
+    let manifest = RawHgChangeset::read(store, cid).ok()?.parse()?.manifest();
+    if manifest.is_null() {
+        Some(GitManifestTree::EMPTY)
+    } else {
+        GitManifestTree::read_treeish(manifest.to_git(store)?)
+    }
+}
+
+/// Returns the changes the given changeset made to the manifest of its
+/// first parent, or to an empty manifest for root changesets.
+fn changeset_manifest_delta(store: &Store, cs: &HgChangesetId) -> Option<Vec<ManifestDiffEntry>> {
+    let cid = cs.to_git(store)?;
+    let commit = RawCommit::read(cid.into())?;
+    let commit = commit.parse()?;
+    let parent_tree = match commit.parents().first() {
+        Some(p) => changeset_manifest_tree(store, GitChangesetId::from_unchecked(*p))?,
+        None => GitManifestTree::EMPTY,
+    };
+    Some(manifest_tree_diff(parent_tree, changeset_manifest_tree(store, cid)?).collect())
+}
+
+/// How a file listed in a changeset was changed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum ChangeKind {
+    Added,
+    Modified,
+    Deleted,
+}
+
+/// Classifies the files listed in the given changeset as added, modified or
+/// deleted, by comparing its manifest with the manifests of its parents.
+fn changeset_file_changes(
+    store: &Store,
+    cs: &HgChangesetId,
+) -> Option<Vec<(Box<[u8]>, ChangeKind)>> {
+    let cid = cs.to_git(store)?;
+    let raw_changeset = RawHgChangeset::read(store, cid).ok()?;
+    let changeset = raw_changeset.parse()?;
+    let commit = RawCommit::read(cid.into())?;
+    let commit = commit.parse()?;
+    let parent_trees = if commit.parents().is_empty() {
+        vec![GitManifestTree::EMPTY]
+    } else {
+        commit
+            .parents()
+            .iter()
+            .map(|p| changeset_manifest_tree(store, GitChangesetId::from_unchecked(*p)))
+            .collect::<Option<Vec<_>>>()?
+    };
+    let diffs = parent_trees
+        .into_iter()
+        .map(|parent_tree| {
+            Some(
+                manifest_tree_diff(parent_tree, changeset_manifest_tree(store, cid)?)
+                    .map(|entry| (entry.path().as_bytes().to_boxed(), entry.into_inner()))
+                    .collect::<BTreeMap<_, _>>(),
+            )
+        })
+        .collect::<Option<Vec<_>>>()?;
+    Some(classify_file_changes(changeset.files(), &diffs))
+}
+
+// Files are classified against the first parent, like Mercurial does. In a
+// merge, a file that doesn't differ from the first parent may still be
+// listed, e.g. when it was removed relative to the second parent, or when
+// the merge resolved to the first parent's version.
+#[allow(clippy::type_complexity)]
+fn classify_file_changes<'a>(
+    files: impl Iterator<Item = &'a [u8]>,
+    diffs: &[BTreeMap<Box<[u8]>, (HgFileId, HgFileId)>],
+) -> Vec<(Box<[u8]>, ChangeKind)> {
+    files
+        .map(|path| {
+            let kind = match diffs[0].get(path) {
+                Some((new, _)) if new.is_null() => ChangeKind::Deleted,
+                Some((_, old)) if old.is_null() => ChangeKind::Added,
+                Some(_) => ChangeKind::Modified,
+                None if diffs[1..]
+                    .iter()
+                    .any(|diff| diff.get(path).is_some_and(|(new, _)| new.is_null())) =>
+                {
+                    ChangeKind::Deleted
+                }
+                None => ChangeKind::Modified,
+            };
+            (path.to_boxed(), kind)
+        })
+        .collect()
+}
+
+#[test]
+fn test_classify_file_changes() {
+    let fid = |n| HgFileId::from_raw_bytes_array([n; 20]);
+    let diff = |entries: &[(&[u8], HgFileId, HgFileId)]| {
+        entries
+            .iter()
+            .map(|(path, new, old)| (path.to_boxed(), (*new, *old)))
+            .collect::<BTreeMap<_, _>>()
+    };
+    let null = HgFileId::NULL;
+    let files = [&b"added"[..], b"modified", b"deleted"];
+
+    let p1 = diff(&[
+        (b"added", fid(1), null),
+        (b"modified", fid(2), fid(3)),
+        (b"deleted", null, fid(4)),
+    ]);
+    assert_eq!(
+        classify_file_changes(files.iter().copied(), &[p1]),
+        [
+            (b"added".to_boxed(), ChangeKind::Added),
+            (b"modified".to_boxed(), ChangeKind::Modified),
+            (b"deleted".to_boxed(), ChangeKind::Deleted),
+        ]
+    );
+
+    // Merge where "added" comes from the second parent, "modified" was
+    // resolved to the first parent's version, and "deleted" was removed
+    // relative to the second parent only.
+    let p1 = diff(&[(b"added", fid(1), null)]);
+    let p2 = diff(&[(b"modified", fid(2), fid(3)), (b"deleted", null, fid(4))]);
+    assert_eq!(
+        classify_file_changes(files.iter().copied(), &[p1, p2]),
+        [
+            (b"added".to_boxed(), ChangeKind::Added),
+            (b"modified".to_boxed(), ChangeKind::Modified),
+            (b"deleted".to_boxed(), ChangeKind::Deleted),
+        ]
+    );
+
+    // A file that is in the first parent but not the second is not an
+    // addition.
+    let p1 = diff(&[]);
+    let p2 = diff(&[(b"added", fid(1), null)]);
+    assert_eq!(
+        classify_file_changes([&b"added"[..]].into_iter(), &[p1, p2]),
+        [(b"added".to_boxed(), ChangeKind::Modified)]
+    );
+}
+
+// Patch files are numbered so that sorting their names gives the order in
+// which they were exported.
+fn patch_file_name(index: usize, cs: &HgChangesetId) -> String {
+    format!("{:04}-{}.patch", index + 1, cs)
+}
+
+#[test]
+fn test_patch_file_name() {
+    let cs = |n| HgChangesetId::from_raw_bytes_array([n; 20]);
+    let names = [cs(0xff), cs(0x11), cs(0x88)]
+        .iter()
+        .enumerate()
+        .map(|(n, cs)| patch_file_name(n, cs))
+        .collect_vec();
+    assert_eq!(
+        names[0],
+        "0001-ffffffffffffffffffffffffffffffffffffffff.patch"
+    );
+    assert!(names.iter().tuple_windows().all(|(a, b)| a < b));
+    assert!(patch_file_name(9, &cs(0)) < patch_file_name(10, &cs(0)));
+}
+
+/// Writes a patch file in `git format-patch` format in `out_dir` for each
+/// changeset that is an ancestor of `heads` but not of `roots`. Files are
+/// numbered in topological order, and their paths returned in that order.
+/// Merges are exported as their changes against their first parent.
+fn export_changeset_patches(
+    store: &Store,
+    roots: &[Abbrev<HgChangesetId>],
+    heads: &[Abbrev<HgChangesetId>],
+    out_dir: &Path,
+) -> Result<Vec<PathBuf>, String> {
+    let to_git = |cs: &Abbrev<HgChangesetId>| {
+        store
+            .hg2git_mut()
+            .get_note_abbrev(*cs)
+            .map(|cid| cid.to_string())
+            .ok_or_else(|| format!("Unknown changeset id: {}", cs))
+    };
+    let mut args = vec!["--topo-order".to_string(), "--reverse".to_string()];
+    for head in heads {
+        args.push(to_git(head)?);
+    }
+    args.push("--not".to_string());
+    for root in roots {
+        args.push(to_git(root)?);
+    }
+    std::fs::create_dir_all(out_dir).map_err(|e| e.to_string())?;
+    let mut result = Vec::new();
+    for (n, cid) in rev_list(args).enumerate() {
+        let cs = GitChangesetId::from_unchecked(cid)
+            .to_hg(store)
+            .ok_or_else(|| format!("Not a mercurial changeset: {}", cid))?;
+        let output = Command::new("git")
+            .args([
+                "log",
+                "-1",
+                "--no-color",
+                "--format=email",
+                "--stat",
+                "--patch",
+                "--diff-merges=first-parent",
+            ])
+            .arg(cid.to_string())
+            .output()
+            .map_err(|e| e.to_string())?;
+        if !output.status.success() {
+            return Err(format!("Failed to create patch for {}", cs));
+        }
+        let path = out_dir.join(patch_file_name(n, &cs));
+        std::fs::write(&path, output.stdout).map_err(|e| e.to_string())?;
+        result.push(path);
+    }
+    Ok(result)
+}
+
+fn do_format_patch(
+    store: &Store,
+    out_dir: &Path,
+    roots: &[Abbrev<HgChangesetId>],
+    heads: &[Abbrev<HgChangesetId>],
+) -> Result<(), String> {
+    let out = stdout();
+    let mut out = BufWriter::new(out.lock());
+    for path in export_changeset_patches(store, roots, heads, out_dir)? {
+        writeln!(out, "{}", path.display()).map_err(|e| e.to_string())?;
+    }
+    Ok(())
+}
+
+fn manifest_diff(a: CommitId, b: CommitId) -> impl Iterator<Item = ManifestDiffEntry> {
     let a = GitManifestTree::read_treeish(GitManifestId::from_unchecked(a)).unwrap();
     let b = GitManifestTree::read_treeish(GitManifestId::from_unchecked(b)).unwrap();
+    manifest_tree_diff(a, b)
+}
+
+fn manifest_tree_diff(
+    a: GitManifestTree,
+    b: GitManifestTree,
+) -> impl Iterator<Item = ManifestDiffEntry> {
     diff_by_path(a, b)
         .recurse()
         .map_map(|entry| match entry {
@@ -3322,6 +4039,39 @@ enum CinnabarCommand {
         #[clap(group = "input")]
         #[clap(help = "Open changelog")]
         changeset: Option<Abbrev<HgChangesetId>>,
+        #[clap(long)]
+        #[clap(requires = "changeset")]
+        #[clap(help = "Only show the changeset extra, one escaped key/value pair per line")]
+        extra: bool,
+        #[clap(long)]
+        #[clap(requires = "changeset")]
+        #[clap(conflicts_with = "extra")]
+        #[clap(help = "Only show the phase hinted at by the changeset extra")]
+        phase: bool,
+        #[clap(long)]
+        #[clap(requires = "changeset")]
+        #[clap(conflicts_with_all = ["extra", "phase"])]
+        #[clap(
+            help = "Only show the files changed in the changeset, as added (A), modified (M) or deleted (D)"
+        )]
+        files: bool,
+        #[clap(long)]
+        #[clap(requires = "changeset")]
+        #[clap(conflicts_with_all = ["extra", "phase", "files"])]
+        #[clap(help = "Only show the patch stored in the changeset metadata, one part per line")]
+        patch: bool,
+        #[clap(long = "manifest")]
+        #[clap(requires = "changeset")]
+        #[clap(conflicts_with_all = ["extra", "phase", "files", "patch"])]
+        #[clap(help = "Only show the manifest of the changeset")]
+        changeset_manifest: bool,
+        #[clap(long)]
+        #[clap(requires = "changeset")]
+        #[clap(conflicts_with_all = ["extra", "phase", "files", "patch", "changeset_manifest"])]
+        #[clap(
+            help = "Only show the manifest changes relative to the first parent, as new and old file revisions"
+        )]
+        manifest_delta: bool,
         #[clap(short = 'm')]
         #[clap(group = "input")]
         #[clap(help = "Open manifest")]
@@ -3329,6 +4079,20 @@ enum CinnabarCommand {
         #[clap(group = "input")]
         #[clap(help = "Open file")]
         file: Option<Abbrev<HgFileId>>,
+        #[clap(long)]
+        #[clap(requires = "file")]
+        #[clap(help = "Only show the file content, without its metadata")]
+        content: bool,
+        #[clap(long)]
+        #[clap(requires = "file")]
+        #[clap(conflicts_with = "content")]
+        #[clap(help = "Only show the file metadata")]
+        metadata: bool,
+        #[clap(long)]
+        #[clap(requires = "file")]
+        #[clap(conflicts_with_all = ["content", "metadata"])]
+        #[clap(help = "Only show the revision and path the file was copied from, if any")]
+        copy: bool,
     },
     #[clap(name = "hg2git")]
     #[clap(group = ArgGroup::new("input").multiple(true).required(true))]
@@ -3364,6 +4128,69 @@ enum CinnabarCommand {
         #[clap(group = "input")]
         #[clap(help = "Read sha1/committish on stdin")]
         batch: bool,
+        #[clap(long)]
+        #[clap(conflicts_with = "abbrev")]
+        #[clap(help = "Convert git blobs to all the file revisions they store")]
+        file: bool,
+    },
+    #[clap(name = "tags")]
+    #[clap(about = "Show mercurial tags and their corresponding git commits")]
+    Tags {
+        #[clap(long)]
+        #[clap(help = "Show the tags as a JSON array")]
+        json: bool,
+        #[clap(long)]
+        #[clap(help = "Show the global tags in the mercurial listkeys format")]
+        #[clap(conflicts_with_all = ["json", "tags"])]
+        listkeys: bool,
+        #[clap(help = "Only show the given tags")]
+        #[clap(value_parser)]
+        tags: Vec<OsString>,
+    },
+    #[clap(name = "branches")]
+    #[clap(about = "Show mercurial branches")]
+    Branches {
+        #[clap(long)]
+        #[clap(help = "Also show branches whose heads are all closed")]
+        #[clap(conflicts_with = "branch")]
+        closed: bool,
+        #[clap(long)]
+        #[clap(help = "Show the most recently added head of each branch")]
+        #[clap(conflicts_with = "branch")]
+        tips: bool,
+        #[clap(help = "Show the changesets on the given branch instead")]
+        #[clap(value_parser)]
+        branch: Option<OsString>,
+    },
+    #[clap(name = "heads")]
+    #[clap(about = "Show mercurial branch heads")]
+    Heads {
+        #[clap(long)]
+        #[clap(help = "Also show heads that close their branch")]
+        closed: bool,
+    },
+    #[clap(name = "stats")]
+    #[clap(about = "Show statistics about the mercurial data")]
+    Stats {
+        #[clap(long)]
+        #[clap(help = "List the changesets whose git commits have the same tree instead")]
+        shared_trees: bool,
+        #[clap(long)]
+        #[clap(conflicts_with = "shared_trees")]
+        #[clap(help = "List all the manifests, parents first, instead")]
+        manifests: bool,
+        #[clap(long)]
+        #[clap(conflicts_with_all = ["shared_trees", "manifests"])]
+        #[clap(help = "Only count the changesets that are ancestors of the given changeset")]
+        head: Vec<Abbrev<HgChangesetId>>,
+        #[clap(long)]
+        #[clap(requires = "head")]
+        #[clap(help = "Don't count the changesets that are ancestors of the given changeset")]
+        common: Vec<Abbrev<HgChangesetId>>,
+        #[clap(long)]
+        #[clap(conflicts_with_all = ["shared_trees", "manifests", "head"])]
+        #[clap(help = "Show statistics about the given changeset instead")]
+        changeset: Option<Abbrev<HgChangesetId>>,
     },
     #[clap(name = "fetch")]
     #[clap(about = "Fetch a changeset from a mercurial remote")]
@@ -3439,6 +4266,18 @@ enum CinnabarCommand {
         #[clap(help = "Type of bundle (bundlespec)")]
         #[clap(conflicts_with = "version")]
         r#type: Option<BundleSpec>,
+        #[clap(long)]
+        #[clap(help = "Write the bundle stored along the metadata by the unbundler check")]
+        #[clap(conflicts_with_all = ["version", "type", "revs", "head"])]
+        stored: bool,
+        #[clap(long)]
+        #[clap(help = "Bundle the given mercurial changeset, along with its ancestors")]
+        #[clap(conflicts_with = "revs")]
+        head: Vec<Abbrev<HgChangesetId>>,
+        #[clap(long)]
+        #[clap(help = "Exclude the ancestors of the given mercurial changeset")]
+        #[clap(requires = "head")]
+        common: Vec<Abbrev<HgChangesetId>>,
         #[clap(help = "Path of the bundle")]
         #[clap(value_parser)]
         path: PathBuf,
@@ -3446,12 +4285,39 @@ enum CinnabarCommand {
         #[clap(value_parser)]
         revs: Vec<OsString>,
     },
+    #[clap(name = "format-patch")]
+    #[clap(about = "Export mercurial changesets as patch files")]
+    FormatPatch {
+        #[clap(short = 'o', long)]
+        #[clap(default_value = ".")]
+        #[clap(help = "Directory where to write the patch files")]
+        #[clap(value_parser)]
+        output_directory: PathBuf,
+        #[clap(long)]
+        #[clap(help = "Exclude the ancestors of the given changeset")]
+        not: Vec<Abbrev<HgChangesetId>>,
+        #[clap(required = true)]
+        #[clap(help = "Mercurial changesets to export, along with their ancestors")]
+        changesets: Vec<Abbrev<HgChangesetId>>,
+    },
+    #[clap(name = "merge-metadata")]
+    #[clap(about = "Merge git-cinnabar metadata from a git repository or bundle")]
+    MergeMetadata {
+        #[clap(help = "Url of the git repository, or path or url of the git bundle")]
+        url: OsString,
+        #[clap(help = "Branch or ref holding the metadata")]
+        #[clap(default_value = "metadata")]
+        branch: OsString,
+    },
     #[clap(name = "unbundle")]
     #[clap(about = "Apply a mercurial bundle to the repository")]
     Unbundle {
         #[clap(long)]
         #[clap(help = "Get clone bundle from given repository")]
         clonebundle: bool,
+        #[clap(long)]
+        #[clap(help = "Only check that the bundle can be applied, without applying it")]
+        check: bool,
         #[clap(help = "Url/Location of the bundle")]
         url: OsString,
     },
@@ -3510,12 +4376,55 @@ fn git_cinnabar(args: Option<&[&OsStr]>) -> Result<c_int, String> {
         RemoteHg { .. } => unreachable!(),
         Setup => unreachable!(),
         Data {
-            changeset: Some(c), ..
-        } => do_data_changeset(&store, c),
+            changeset: Some(c),
+            extra,
+            phase,
+            files,
+            patch,
+            changeset_manifest,
+            manifest_delta,
+            ..
+        } => do_data_changeset(
+            &store,
+            c,
+            if extra {
+                ChangesetData::Extra
+            } else if phase {
+                ChangesetData::Phase
+            } else if files {
+                ChangesetData::Files
+            } else if patch {
+                ChangesetData::Patch
+            } else if changeset_manifest {
+                ChangesetData::Manifest
+            } else if manifest_delta {
+                ChangesetData::ManifestDelta
+            } else {
+                ChangesetData::Raw
+            },
+        ),
         Data {
             manifest: Some(m), ..
         } => do_data_manifest(&store, m),
-        Data { file: Some(f), .. } => do_data_file(&store, f),
+        Data {
+            file: Some(f),
+            content,
+            metadata,
+            copy,
+            ..
+        } => do_data_file(
+            &store,
+            f,
+            if content {
+                FileData::Content
+            } else if metadata {
+                FileData::Metadata
+            } else if copy {
+                FileData::Copy
+            } else {
+                FileData::Raw
+            },
+        ),
         Data { .. } => unreachable!(),
         Hg2Git {
             abbrev,
@@ -3532,13 +4441,42 @@ fn git_cinnabar(args: Option<&[&OsStr]>) -> Result<c_int, String> {
             abbrev,
             committish,
             batch,
-        } => do_conversion_cmd(
-            &store,
-            abbrev.map(|v| v.first().map_or(12, |a| a.0)),
-            committish.into_iter(),
-            batch,
-            do_one_git2hg,
-        ),
+            file,
+        } => {
+            let abbrev = abbrev.map(|v| v.first().map_or(12, |a| a.0));
+            if file {
+                // Finding the file revisions for a blob requires going
+                // through all of hg2git, so only do it once.
+                let files = GitFileId::to_hg_map(&store);
+                do_conversion_cmd(
+                    &store,
+                    abbrev,
+                    committish.into_iter(),
+                    batch,
+                    |_, blobish| do_one_git2hg_file(&files, blobish),
+                )
+            } else {
+                do_conversion_cmd(&store, abbrev, committish.into_iter(), batch, do_one_git2hg)
+            }
+        }
+        Tags {
+            json,
+            listkeys,
+            tags,
+        } => do_tags(&store, json, listkeys, tags),
+        Branches {
+            closed,
+            tips,
+            branch,
+        } => do_branches(&store, closed, tips, branch),
+        Heads { closed } => do_heads(&store, closed),
+        Stats {
+            shared_trees,
+            manifests,
+            head,
+            common,
+            changeset,
+        } => do_stats(&store, shared_trees, manifests, head, common, changeset),
         Fetch {
             remote: Some(remote),
             revs,
@@ -3554,7 +4492,17 @@ fn git_cinnabar(args: Option<&[&OsStr]>) -> Result<c_int, String> {
             committish,
         } => do_rollback(candidates, fsck, force, committish),
         Upgrade => do_upgrade(),
-        Unbundle { clonebundle, url } => do_unbundle(&mut store, clonebundle, url),
+        Unbundle {
+            clonebundle,
+            check,
+            url,
+        } => do_unbundle(&mut store, clonebundle, check, url),
+        MergeMetadata { url, branch } => do_merge_metadata(&mut store, url, branch),
+        FormatPatch {
+            output_directory,
+            not,
+            changesets,
+        } => do_format_patch(&store, &output_directory, &not, &changesets),
         Fsck {
             force,
             full,
@@ -3563,12 +4511,21 @@ fn git_cinnabar(args: Option<&[&OsStr]>) -> Result<c_int, String> {
             Ok(code) => return Ok(code),
             Err(e) => Err(e),
         },
+        Bundle {
+            stored: true, path, ..
+        } => match do_stored_bundle(&store, path) {
+            Ok(code) => return Ok(code),
+            Err(e) => Err(e),
+        },
         Bundle {
             version,
             r#type,
+            stored: false,
+            head,
+            common,
             path,
             revs,
-        } => match do_bundle(&store, version, r#type, path, revs) {
+        } => match do_bundle(&store, version, r#type, path, &head, &common, revs) {
             Ok(code) => return Ok(code),
             Err(e) => Err(e),
         },
@@ -3933,6 +4890,7 @@ fn remote_helper_import(
         .map_err(|refname| format!("couldn't find remote ref {}", refname))?;
 
     let mut tags = None;
+    let mut tags_cache = TagsCache::default();
     let unknown_wanted_heads = wanted_refs
         .iter()
         .filter(|(_, _, cid)| cid.is_none())
@@ -3940,7 +4898,7 @@ fn remote_helper_import(
         .unique()
         .collect_vec();
     if !unknown_wanted_heads.is_empty() {
-        tags = Some(store.get_tags());
+        tags = Some(store.get_tags_cached(&mut tags_cache));
         if graft_config_enabled(remote)?.unwrap_or(false) {
             init_graft(store);
         }
@@ -3992,7 +4950,7 @@ fn remote_helper_import(
     }
 
     if let Some(old_tags) = tags {
-        if old_tags != store.get_tags() {
+        if old_tags != store.get_tags_cached(&mut tags_cache) {
             eprintln!(
                 "\nRun the following command to update tags:\n\
                  \x20 git cinnabar fetch --tags"
@@ -4150,8 +5108,12 @@ fn remote_helper_push(
                     "--max-parents=0",
                     "refs/cinnabar/metadata^",
                 ])
-                .filter_map(|c| GitChangesetId::from_unchecked(c).to_hg(store))
+                .map(GitChangesetId::from_unchecked)
                 .collect_vec();
+                let cinnabar_roots = GitChangesetId::to_hg_batch(&cinnabar_roots, store)
+                    .into_iter()
+                    .flatten()
+                    .collect_vec();
                 fail = !conn.known(&cinnabar_roots).iter().any(|k| *k);
             }
             if fail {
@@ -4511,6 +5473,11 @@ fn git_remote_hg(remote: OsString, mut url: OsString) -> Result<c_int, String> {
                     [] => false,
                     _ => panic!("unknown argument(s) to list command"),
                 };
+                // The metadata may have been changed by something else since
+                // the previous command.
+                if let Ok(store) = store.as_mut() {
+                    store.resync_changeset_heads();
+                }
                 if url.scheme() == "tags" {
                     assert!(!for_push);
                     remote_helper_tags_list(
@@ -4715,6 +5682,12 @@ bitflags! {
         const CINNABARCLONE = 0x800;
         const CLONEBUNDLES = 0x1000;
         const UNBUNDLER = 0x2000;
+        const NO_HEADS_CACHE = 0x4000;
+        const THREADED_FILES = 0x8000;
+        const STRICT_FILE_METADATA = 0x10000;
+        const TAG_CONFLICTS = 0x20000;
+        const CHANGESET_HASH = 0x40000;
+        const STRICT_CHANGESET_PATCH = 0x80000;
 
         const ALL_BASE_CHECKS = Checks::NODEID.bits() | Checks::MANIFESTS.bits() | Checks::HELPER.bits();
     }
@@ -4737,6 +5710,12 @@ static CHECKS: Lazy<Checks> = Lazy::new(|| {
                 b"cinnabarclone" => checks.set(Checks::CINNABARCLONE, true),
                 b"clonebundles" => checks.set(Checks::CLONEBUNDLES, true),
                 b"unbundler" => checks.set(Checks::UNBUNDLER, true),
+                b"no-heads-cache" => checks.set(Checks::NO_HEADS_CACHE, true),
+                b"threaded-files" => checks.set(Checks::THREADED_FILES, true),
+                b"strict-file-metadata" => checks.set(Checks::STRICT_FILE_METADATA, true),
+                b"tag-conflicts" => checks.set(Checks::TAG_CONFLICTS, true),
+                b"changeset-hash" => checks.set(Checks::CHANGESET_HASH, true),
+                b"strict-changeset-patch" => checks.set(Checks::STRICT_CHANGESET_PATCH, true),
                 _ => {}
             }
         }
@@ -3,13 +3,35 @@
  * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
 
 use std::iter::Enumerate;
+use std::str::FromStr;
 use std::sync::atomic::{AtomicBool, Ordering};
-use std::time::Instant;
+use std::time::{Duration, Instant};
 
-use crate::{check_enabled, Checks};
+use once_cell::sync::Lazy;
+
+use crate::{check_enabled, get_config, Checks};
 
 static PROGRESS_ENABLED: AtomicBool = AtomicBool::new(true);
 
+// Minimum time between two updates of the progress display, so that fast
+// operations don't spend their time formatting and writing progress.
+static PROGRESS_INTERVAL: Lazy<Duration> = Lazy::new(|| {
+    get_config("progress-interval").map_or(Duration::from_millis(100), |interval| {
+        interval
+            .to_str()
+            .and_then(|s| u64::from_str(s).ok())
+            .map(Duration::from_millis)
+            .unwrap_or_else(|| {
+                warn!(
+                    target: "root",
+                    "Ignoring invalid progress-interval: {}",
+                    interval.to_string_lossy()
+                );
+                Duration::from_millis(100)
+            })
+    })
+});
+
 pub fn progress_enabled() -> bool {
     PROGRESS_ENABLED.load(Ordering::Relaxed)
 }
@@ -20,6 +42,15 @@ pub fn set_progress(value: bool) {
 
 pub trait Progress: Iterator + Sized {
     fn progress<F: Fn(usize) -> String>(self, formatter: F) -> ProgressIter<Self, F>;
+
+    /// Like `progress`, but for when the total number of items is known
+    /// ahead of time, in which case the completion percentage, the rate
+    /// and an estimated time remaining are displayed too.
+    fn progress_with_total<F: Fn(usize) -> String>(
+        self,
+        total: usize,
+        formatter: F,
+    ) -> ProgressIter<Self, F>;
 }
 
 enum ProgressIterImpl<I: Iterator, F: Fn(usize) -> String> {
@@ -30,8 +61,11 @@ enum ProgressIterImpl<I: Iterator, F: Fn(usize) -> String> {
 struct ProgressIterEnabled<I: Iterator, F: Fn(usize) -> String> {
     iter: Enumerate<I>,
     formatter: F,
+    total: Option<usize>,
+    created: Instant,
     start: Option<Instant>,
     last_update: Instant,
+    interval: Duration,
     count: usize,
 }
 
@@ -39,27 +73,100 @@ pub struct ProgressIter<I: Iterator, F: Fn(usize) -> String>(ProgressIterImpl<I,
 
 impl<I: Iterator> Progress for I {
     fn progress<F: Fn(usize) -> String>(self, formatter: F) -> ProgressIter<Self, F> {
-        if PROGRESS_ENABLED.load(Ordering::Relaxed) {
-            let now = Instant::now();
+        ProgressIter::new(self, None, formatter)
+    }
 
-            let this = ProgressIterEnabled {
-                iter: self.enumerate(),
-                formatter,
-                start: check_enabled(Checks::TIME).then_some(now),
-                last_update: now,
-                count: 0,
-            };
+    fn progress_with_total<F: Fn(usize) -> String>(
+        self,
+        total: usize,
+        formatter: F,
+    ) -> ProgressIter<Self, F> {
+        ProgressIter::new(self, Some(total), formatter)
+    }
+}
+
+impl<I: Iterator, F: Fn(usize) -> String> ProgressIter<I, F> {
+    fn new(iter: I, total: Option<usize>, formatter: F) -> Self {
+        if PROGRESS_ENABLED.load(Ordering::Relaxed) {
+            let this = ProgressIterEnabled::new(iter, total, formatter, *PROGRESS_INTERVAL);
             ProgressIter(ProgressIterImpl::Enabled(this))
         } else {
-            ProgressIter(ProgressIterImpl::Disabled(self))
+            ProgressIter(ProgressIterImpl::Disabled(iter))
         }
     }
 }
 
+fn format_duration(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    if secs >= 3600 {
+        format!("{}h{:02}m{:02}s", secs / 3600, secs / 60 % 60, secs % 60)
+    } else if secs >= 60 {
+        format!("{}m{:02}s", secs / 60, secs % 60)
+    } else {
+        format!("{secs}s")
+    }
+}
+
+fn format_total(count: usize, total: usize, elapsed: Duration) -> String {
+    let percent = (count.min(total) * 100).checked_div(total).unwrap_or(100);
+    let mut result = format!("{count}/{total} ({percent}%)");
+    let elapsed_secs = elapsed.as_secs_f64();
+    if count > 0 && elapsed_secs > 0.0 {
+        let rate = count as f64 / elapsed_secs;
+        result.push_str(&format!(" ~{rate:.0}/s"));
+        if count < total {
+            let eta = Duration::from_secs_f64((total - count) as f64 / rate);
+            result.push_str(&format!(" ETA {}", format_duration(eta)));
+        }
+    }
+    result
+}
+
+#[test]
+fn test_format_total() {
+    assert_eq!(format_total(0, 100, Duration::ZERO), "0/100 (0%)");
+    assert_eq!(format_total(0, 100, Duration::from_secs(1)), "0/100 (0%)");
+    assert_eq!(
+        format_total(25, 100, Duration::from_secs(5)),
+        "25/100 (25%) ~5/s ETA 15s"
+    );
+    assert_eq!(
+        format_total(10, 1000, Duration::from_secs(2)),
+        "10/1000 (1%) ~5/s ETA 3m18s"
+    );
+    assert_eq!(
+        format_total(1, 10000, Duration::from_secs(1)),
+        "1/10000 (0%) ~1/s ETA 2h46m39s"
+    );
+    assert_eq!(
+        format_total(100, 100, Duration::from_secs(4)),
+        "100/100 (100%) ~25/s"
+    );
+    assert_eq!(format_total(0, 0, Duration::ZERO), "0/0 (100%)");
+}
+
 impl<I: Iterator, F: Fn(usize) -> String> ProgressIterEnabled<I, F> {
+    fn new(iter: I, total: Option<usize>, formatter: F, interval: Duration) -> Self {
+        let now = Instant::now();
+        ProgressIterEnabled {
+            iter: iter.enumerate(),
+            formatter,
+            total,
+            created: now,
+            start: check_enabled(Checks::TIME).then_some(now),
+            last_update: now,
+            interval,
+            count: 0,
+        }
+    }
+
     #[inline]
     fn display(&mut self, now: Instant) {
-        let s = (self.formatter)(self.count);
+        let mut s = (self.formatter)(self.count);
+        if let Some(total) = self.total {
+            s.push_str(": ");
+            s.push_str(&format_total(self.count, total, now - self.created));
+        }
         if let Some(start) = self.start {
             eprint!("\r{} in {:.1}s", s, (now - start).as_secs_f32());
         } else {
@@ -85,7 +192,7 @@ impl<I: Iterator, F: Fn(usize) -> String> Iterator for ProgressIterEnabled<I, F>
         self.iter.next().map(|(n, item)| {
             let now = Instant::now();
             self.count = n + 1;
-            if (now - self.last_update).as_millis() > 100 {
+            if now - self.last_update > self.interval {
                 self.display(now);
             }
             item
@@ -103,3 +210,29 @@ impl<I: Iterator, F: Fn(usize) -> String> Iterator for ProgressIter<I, F> {
         }
     }
 }
+
+#[test]
+fn test_progress_interval() {
+    use std::cell::Cell;
+
+    let displays = Cell::new(0);
+    let interval = Duration::from_millis(10);
+    let start = Instant::now();
+    let duration = Duration::from_millis(100);
+    let items = std::iter::from_fn(|| (start.elapsed() < duration).then_some(()));
+    let mut progress = ProgressIterEnabled::new(
+        items,
+        None,
+        |n| {
+            displays.set(displays.get() + 1);
+            format!("{n} items")
+        },
+        interval,
+    );
+    let count = progress.by_ref().count();
+    let elapsed = start.elapsed();
+    let max_displays = (elapsed.as_millis() / interval.as_millis()) as usize;
+    assert_gt!(displays.get(), 0);
+    assert_le!(displays.get(), max_displays);
+    assert_gt!(count, max_displays);
+}
@@ -4,6 +4,7 @@
 
 use std::ffi::c_void;
 use std::marker::PhantomData;
+use std::ops::Range;
 use std::os::raw::{c_char, c_int, c_long, c_ulong};
 
 use bstr::ByteSlice;
@@ -173,6 +174,131 @@ pub fn textdiff<'a>(a: &[u8], b: &'a [u8]) -> impl Iterator<Item = PatchInfo<&'a
     ctx.patch_info.into_iter()
 }
 
+// Hunks from textdiff larger than this are not refined further.
+const BYTEDIFF_MAX_HUNK: usize = 8192;
+// Maximum number of byte insertions and deletions for a given hunk. Beyond
+// that, the hunk is not refined further.
+const BYTEDIFF_MAX_EDITS: usize = 256;
+// Edits separated by fewer identical bytes than this are merged into a
+// single hunk, because each hunk has a cost of its own when serialized.
+const BYTEDIFF_MIN_GAP: usize = 8;
+
+/// Like `textdiff`, but each hunk is refined to only cover the bytes that
+/// actually changed.
+pub fn bytediff<'a>(a: &[u8], b: &'a [u8]) -> impl Iterator<Item = PatchInfo<&'a [u8]>> {
+    let mut result = Vec::new();
+    for p in textdiff(a, b) {
+        let orig = &a[p.start..p.end];
+        let patched = p.data;
+        let prefix = Iterator::zip(orig.iter(), patched.iter())
+            .take_while(|(a, b)| a == b)
+            .count();
+        let (orig, patched) = (&orig[prefix..], &patched[prefix..]);
+        let suffix = Iterator::zip(orig.iter().rev(), patched.iter().rev())
+            .take_while(|(a, b)| a == b)
+            .count();
+        let (orig, patched) = (
+            &orig[..orig.len() - suffix],
+            &patched[..patched.len() - suffix],
+        );
+        let start = p.start + prefix;
+        let edits = (!orig.is_empty()
+            && !patched.is_empty()
+            && orig.len() + patched.len() <= BYTEDIFF_MAX_HUNK)
+            .then(|| byte_edits(orig, patched))
+            .flatten();
+        if let Some(edits) = edits {
+            result.extend(edits.into_iter().map(|(a, b)| PatchInfo {
+                start: start + a.start,
+                end: start + a.end,
+                data: &patched[b],
+            }));
+        } else {
+            result.push(PatchInfo {
+                start,
+                end: start + orig.len(),
+                data: patched,
+            });
+        }
+    }
+    result.into_iter()
+}
+
+// Myers' diff algorithm, at the byte level. Returns the pairs of ranges in
+// `a` and `b` that differ, or None if there are more than BYTEDIFF_MAX_EDITS
+// edits.
+fn byte_edits(a: &[u8], b: &[u8]) -> Option<Vec<(Range<usize>, Range<usize>)>> {
+    let (n, m) = (a.len() as isize, b.len() as isize);
+    let max = (a.len() + b.len()).min(BYTEDIFF_MAX_EDITS) as isize;
+    let offset = max + 1;
+    let mut v = vec![0isize; 2 * max as usize + 3];
+    // For each d, the furthest reaching paths for diagonals -d..=d, before
+    // round d.
+    let mut trace = Vec::new();
+    let mut found = false;
+    'outer: for d in 0..=max {
+        trace.push(v[(offset - d) as usize..=(offset + d) as usize].to_vec());
+        for k in (-d..=d).step_by(2) {
+            let idx = (k + offset) as usize;
+            let mut x = if k == -d || (k != d && v[idx - 1] < v[idx + 1]) {
+                v[idx + 1]
+            } else {
+                v[idx - 1] + 1
+            };
+            let mut y = x - k;
+            while x < n && y < m && a[x as usize] == b[y as usize] {
+                x += 1;
+                y += 1;
+            }
+            v[idx] = x;
+            if x >= n && y >= m {
+                found = true;
+                break 'outer;
+            }
+        }
+    }
+    if !found {
+        return None;
+    }
+
+    let mut edits = Vec::new();
+    let (mut x, mut y) = (n, m);
+    for (d, v) in trace.iter().enumerate().skip(1).rev() {
+        let d = d as isize;
+        let k = x - y;
+        let get = |k: isize| v[(k + d) as usize];
+        let prev_k = if k == -d || (k != d && get(k - 1) < get(k + 1)) {
+            k + 1
+        } else {
+            k - 1
+        };
+        let prev_x = get(prev_k);
+        let prev_y = prev_x - prev_k;
+        // Moving down from the k + 1 diagonal is an insertion, moving right
+        // from the k - 1 diagonal is a deletion. What follows is identical.
+        let mid_x = if prev_k == k + 1 { prev_x } else { prev_x + 1 };
+        let mid_y = mid_x - k;
+        edits.push((
+            prev_x as usize..mid_x as usize,
+            prev_y as usize..mid_y as usize,
+        ));
+        x = prev_x;
+        y = prev_y;
+    }
+
+    let mut result: Vec<(Range<usize>, Range<usize>)> = Vec::new();
+    for (a, b) in edits.into_iter().rev() {
+        match result.last_mut() {
+            Some((last_a, last_b)) if a.start - last_a.end < BYTEDIFF_MIN_GAP => {
+                last_a.end = a.end;
+                last_b.end = b.end;
+            }
+            _ => result.push((a, b)),
+        }
+    }
+    Some(result)
+}
+
 #[test]
 fn test_textdiff() {
     let a = ["foo", "bar", "baz", "qux"].join("\n");
@@ -223,3 +349,72 @@ fn test_textdiff() {
         ]
     );
 }
+
+#[test]
+fn test_bytediff() {
+    let check = |a: &str, b: &str, expected: Vec<PatchInfo<&str>>| {
+        let result = bytediff(a.as_bytes(), b.as_bytes()).collect::<Vec<_>>();
+        assert_eq!(result, expected);
+        assert_eq!(
+            apply(result.into_iter(), a.as_bytes()).as_bstr(),
+            b.as_bytes().as_bstr()
+        );
+    };
+
+    let a = ["foo", "bar baz qux", "hoge"].join("\n");
+    check(
+        &a,
+        &["foo", "bar bAz qux", "hoge"].join("\n"),
+        vec![PatchInfo {
+            start: 9,
+            end: 10,
+            data: "A",
+        }],
+    );
+    check(
+        &a,
+        &["foo", "bar baz  qux", "hoge"].join("\n"),
+        vec![PatchInfo {
+            start: 12,
+            end: 12,
+            data: " ",
+        }],
+    );
+    // Close edits are merged.
+    check(
+        &a,
+        &["foo", "bar  baz  qux", "hoge"].join("\n"),
+        vec![PatchInfo {
+            start: 8,
+            end: 11,
+            data: " baz ",
+        }],
+    );
+    // Edits further apart are not.
+    check(
+        &a,
+        &["fooo", "bar baz qux", "hog"].join("\n"),
+        vec![
+            PatchInfo {
+                start: 3,
+                end: 3,
+                data: "o",
+            },
+            PatchInfo {
+                start: 19,
+                end: 20,
+                data: "",
+            },
+        ],
+    );
+    check(
+        &a,
+        &["foo", "bar baz qux", "hoge", "fuga"].join("\n"),
+        vec![PatchInfo {
+            start: 20,
+            end: 20,
+            data: "\nfuga",
+        }],
+    );
+    check(&a, &a, vec![]);
+}
@@ -0,0 +1,114 @@
+  $ PATH=$TESTDIR/..:$PATH
+
+Test repository setup.
+
+  $ n=0
+  $ create() {
+  >   echo $1 > $1
+  >   hg add $1
+  >   hg commit -q -m $1 -u nobody -d "$n 0"
+  >   n=$(expr $n + 1)
+  > }
+
+  $ hg init repo
+  $ REPO=$(pwd)/repo
+
+The foo branch is closed at its only head. The bar branch has two heads, only
+one of which is closed.
+
+  $ cd repo
+  $ create a
+  $ hg branch -q foo
+  $ create b
+  $ hg commit -q --close-branch -m close-foo -u nobody -d "$n 0"
+  $ hg update -q default
+  $ hg branch -q bar
+  $ create c
+  $ create d
+  $ hg update -q -r 'p1(.)'
+  $ create e
+  $ hg commit -q --close-branch -m close-bar -u nobody -d "$n 0"
+  $ cd ..
+
+  $ git clone -q hg::$REPO repo-git
+
+Fully closed branches are only listed with --closed.
+
+  $ git -C repo-git cinnabar branches
+  bar
+  default
+  $ git -C repo-git cinnabar branches --closed
+  bar
+  default
+  foo (closed)
+
+Branches can be listed along with their tip, which is their most recently added
+head.
+
+  $ git -C repo-git cinnabar branches --tips --closed | sed 's/ (closed)$//' > tips
+  $ for b in bar default foo; do echo "$(hg -R repo log -r "max(head() and branch($b))" -T '{node}') $b"; done > tips-expected
+  $ cmp tips tips-expected
+  $ git -C repo-git cinnabar branches --tips | wc -l
+  2
+
+The same branches are closed whether the changeset heads come from the cache or
+from the changesets metadata.
+
+  $ git -C repo-git rev-parse -q --verify refs/cinnabar/changeset-heads > /dev/null
+  $ GIT_CINNABAR_CHECK=no-heads-cache git -C repo-git cinnabar branches --closed
+  bar
+  default
+  foo (closed)
+
+The changesets on a given branch can be listed, parents first, along with their
+git commits. This doesn't depend on the changeset heads cache either.
+
+  $ git -C repo-git cinnabar branches default
+  f92470d7f6966a39dfbced6a525fe81ebf5c37b9 [0-9a-f]{40} (re)
+  $ git -C repo-git cinnabar branches bar | cut -d ' ' -f 1 | sort > bar
+  $ hg -R repo log -b bar -T '{node}\n' | sort > bar-expected
+  $ cmp bar bar-expected
+  $ test "$(git -C repo-git cinnabar branches bar | head -n 1 | cut -d ' ' -f 1)" = "$(hg -R repo log -r 'min(branch(bar))' -T '{node}')"
+  $ GIT_CINNABAR_CHECK=no-heads-cache git -C repo-git cinnabar branches bar | cut -d ' ' -f 1 | sort > bar-no-cache
+  $ cmp bar bar-no-cache
+  $ git -C repo-git cinnabar branches foo | wc -l
+  2
+  $ git -C repo-git cinnabar branches qux
+
+Branch heads that close their branch are only listed with --closed.
+
+  $ git -C repo-git cinnabar heads | cut -d ' ' -f 3- | sort
+  bar
+  default
+  $ git -C repo-git cinnabar heads --closed | cut -d ' ' -f 3- | sort
+  bar
+  bar (closed)
+  default
+  foo (closed)
+  $ git -C repo-git cinnabar heads | cut -d ' ' -f 1 | sort > heads
+  $ hg -R repo heads -T '{node}\n' | sort > heads-expected
+  $ cmp heads heads-expected
+  $ git -C repo-git cinnabar heads --closed | cut -d ' ' -f 1 | sort > heads
+  $ hg -R repo heads --closed -T '{node}\n' | sort > heads-expected
+  $ cmp heads heads-expected
+
+Branch names are given escaped, the way they are stored in changesets, so that
+all the changesets of a branch with special characters stay on one branch.
+
+  $ cd repo
+  $ hg update -q default
+  $ hg branch -q 'back\slash'
+  $ create f
+  $ create g
+  $ cd ..
+  $ git -C repo-git fetch -q origin
+  $ git -C repo-git cinnabar branches
+  back\\slash
+  bar
+  default
+  $ git -C repo-git cinnabar heads | grep -c slash
+  1
+  $ git -C repo-git cinnabar branches 'back\\slash' | wc -l
+  2
+  $ git -C repo-git cinnabar data -c $(hg -R repo log -r tip -T '{node}') --extra
+  branch:back\\slash
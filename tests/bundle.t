@@ -0,0 +1,85 @@
+  $ PATH=$TESTDIR/..:$PATH
+
+Test repository setup.
+
+  $ n=0
+  $ create() {
+  >   echo $1 > $1
+  >   hg add $1
+  >   hg commit -q -m $1 -u nobody -d "$n 0"
+  >   n=$(expr $n + 1)
+  > }
+
+  $ hg init repo
+  $ REPO=$(pwd)/repo
+
+The default branch and the foo branch only share their root.
+
+  $ cd repo
+  $ create a
+  $ create b
+  $ hg update -q -r 0
+  $ hg branch -q foo
+  $ create c
+  $ create d
+  $ B=$(hg log -r 1 -T '{node}')
+  $ D=$(hg log -r 3 -T '{node}')
+  $ cd ..
+
+  $ git clone -q hg::$REPO repo-git
+  $ GIT_B=$(git -C repo-git cinnabar hg2git $B)
+  $ GIT_D=$(git -C repo-git cinnabar hg2git $D)
+
+An empty range gives a bundle without changesets.
+
+  $ git -C repo-git cinnabar bundle $(pwd)/empty.hg $GIT_B ^$GIT_B > /dev/null 2>&1
+  $ hg clone -q -r $B repo repo-b
+  $ hg -R repo-b unbundle -q empty.hg
+  $ hg -R repo-b log -T '{desc}\n'
+  b
+  a
+  $ git init -q repo-empty
+  $ git -C repo-empty cinnabar unbundle --check $(pwd)/empty.hg 2> /dev/null
+
+A range that excludes one branch only contains the changesets of the other
+one that are not ancestors of the excluded one.
+
+  $ git -C repo-git cinnabar bundle $(pwd)/foo.hg $GIT_D ^$GIT_B > /dev/null 2>&1
+  $ hg -R repo-b unbundle -q foo.hg
+  $ hg -R repo-b log -r "not ::$B" -T '{desc}\n'
+  c
+  d
+  $ test "$(hg -R repo-b log -r tip -T '{node}')" = "$D"
+
+Such a bundle applies on top of the excluded branch.
+
+  $ git init -q repo-foo
+  $ git -C repo-foo cinnabar fetch hg::$REPO $B > /dev/null 2>&1
+  $ git -C repo-foo cinnabar unbundle $(pwd)/foo.hg > /dev/null 2>&1
+  $ test "$(git -C repo-foo cinnabar hg2git $D)" = "$GIT_D"
+
+Bundles can also be created from a range of mercurial changesets.
+
+  $ git -C repo-git cinnabar bundle -t zstd-v2 --head $B --common $B $(pwd)/empty-zstd.hg > /dev/null 2>&1
+  $ head -c 4 empty-zstd.hg
+  HG20 (no-eol)
+  $ hg -R repo-b unbundle -q empty-zstd.hg
+  $ git -C repo-empty cinnabar unbundle --check $(pwd)/empty-zstd.hg 2> /dev/null
+
+  $ git -C repo-git cinnabar bundle -t zstd-v2 --head $D --common $B $(pwd)/foo-zstd.hg > /dev/null 2>&1
+  $ hg clone -q -r $B repo repo-b2
+  $ hg -R repo-b2 unbundle -q foo-zstd.hg
+  $ hg -R repo-b2 log -r "not ::$B" -T '{desc}\n'
+  c
+  d
+  $ git init -q repo-foo2
+  $ git -C repo-foo2 cinnabar fetch hg::$REPO $B > /dev/null 2>&1
+  $ git -C repo-foo2 cinnabar unbundle $(pwd)/foo-zstd.hg > /dev/null 2>&1
+  $ test "$(git -C repo-foo2 cinnabar hg2git $D)" = "$GIT_D"
+
+Changesets that are not known are reported.
+
+  $ git -C repo-git cinnabar bundle --head 0123456 $(pwd)/unknown.hg
+  \r (no-eol) (esc)
+  ERROR Unknown changeset id: 0123456
+  [1]
@@ -0,0 +1,40 @@
+  $ PATH=$TESTDIR/..:$PATH
+
+Create a bundle with a changeset whose list of files is not sorted. Mercurial
+itself wouldn't create such a changeset, and git-cinnabar needs a patch to
+recreate it from its git commit.
+
+  $ python3 $TESTDIR/mkbundle.py bundle.hg a='a\n' b='b\n' --changeset 'nobody\n0 0\nb\na\n\nfoo'
+  adb3987a7078e1cd4050d5e8255da4fc6b287a57
+  b789fdd96dc2f3bd229c1dd8eedf0fc60e2b68e3 a
+  1e88685f5ddec574a34c70af492f95b6debc8741 b
+
+With the strict-changeset-patch check, the import aborts.
+
+  $ git init -q repo-strict
+  $ GIT_CINNABAR_CHECK=strict-changeset-patch git -C repo-strict cinnabar unbundle $(pwd)/bundle.hg 2>&1 | grep -o '^fatal: .*'
+  fatal: Changeset adb3987a7078e1cd4050d5e8255da4fc6b287a57 can't be stored faithfully as a git commit
+  $ git -C repo-strict cinnabar hg2git adb3987a7078e1cd4050d5e8255da4fc6b287a57
+  0000000000000000000000000000000000000000
+
+Otherwise, the changeset is stored along with the patch, and recreated
+identically.
+
+  $ git init -q repo
+  $ git -C repo cinnabar unbundle $(pwd)/bundle.hg > /dev/null 2>&1
+  $ git -C repo cinnabar git2hg $(git -C repo cinnabar hg2git adb3987a7078e1cd4050d5e8255da4fc6b287a57)
+  adb3987a7078e1cd4050d5e8255da4fc6b287a57
+  $ git -C repo cat-file blob refs/cinnabar/metadata^4:$(git -C repo cinnabar hg2git adb3987a7078e1cd4050d5e8255da4fc6b287a57) | grep -c '^patch '
+  1
+  $ git -C repo cinnabar data -c adb3987a7078e1cd4050d5e8255da4fc6b287a57 | tail -n +4
+  b
+  a
+  
+  foo (no-eol)
+
+The patch can be shown, one part per line. Here, "a" is moved after "b" in the
+list of files.
+
+  $ git -C repo cinnabar data -c adb3987a7078e1cd4050d5e8255da4fc6b287a57 --patch
+  52 54 ""
+  56 56 "a\n"
@@ -0,0 +1,129 @@
+  $ PATH=$TESTDIR/..:$PATH
+
+Test repository setup.
+
+  $ hg init repo
+  $ REPO=$(pwd)/repo
+
+  $ cd repo
+  $ echo a > a
+  $ hg add a
+  $ hg commit -q -m a -u nobody -d "0 0"
+  $ hg branch -q foo
+  $ echo b > b
+  $ hg add b
+  $ hg commit -q -m b -u nobody -d "1 0" --close-branch
+  $ NODE=$(hg log -T '{node}' -r .)
+  $ cd ..
+
+  $ git clone -q hg::$REPO repo-git 2> /dev/null
+
+The changeset extra can be shown alone, one key/value pair per line, sorted.
+
+  $ git -C repo-git cinnabar data -c $NODE --extra
+  branch:foo
+  close:1
+
+Changesets without extra show nothing.
+
+  $ git -C repo-git cinnabar data -c f92470d7f6966a39dfbced6a525fe81ebf5c37b9 --extra
+
+The option requires a changeset.
+
+  $ git -C repo-git cinnabar data -m f92470d7f6966a39dfbced6a525fe81ebf5c37b9 --extra > /dev/null 2>&1
+  [1]
+
+Mercurial doesn't record phases in changesets, but some tools record a phase
+in the changeset extra, which can be shown with --phase.
+
+  $ python3 $TESTDIR/mkbundle.py phase.hg foo='foo\n' --changeset 'nobody\n0 0 phase:draft\nfoo\n\nfoo'
+  6ce4542f1ca7e70ad5f3c49e1331bb840b186143
+  2ed2a3912a0b24502043eae84ee4b279c18b90dd foo
+
+  $ git init -q repo-phase
+  $ git -C repo-phase cinnabar unbundle $(pwd)/phase.hg > /dev/null 2>&1
+  $ git -C repo-phase cinnabar data -c 6ce4542f1ca7e70ad5f3c49e1331bb840b186143 --phase
+  draft
+
+The phase is unknown for changesets that don't record one.
+
+  $ git -C repo-git cinnabar data -c $NODE --phase
+  unknown
+
+File revisions recording a copy have metadata, which can be shown separately
+from the file content.
+
+  $ cd repo
+  $ hg update -q default
+  $ hg cp a c
+  $ hg commit -q -m c -u nobody -d "2 0"
+  $ NODE=$(hg log -T '{node}' -r .)
+  $ FILE=$(hg manifest --debug | grep ' c$' | cut -c 1-40)
+  $ cd ..
+  $ git -C repo-git cinnabar fetch hg::$REPO $NODE > /dev/null 2>&1
+
+  $ git -C repo-git cinnabar data $FILE
+  \x01 (esc)
+  copy: a
+  copyrev: b789fdd96dc2f3bd229c1dd8eedf0fc60e2b68e3
+  \x01 (esc)
+  a
+  $ git -C repo-git cinnabar data $FILE --content
+  a
+  $ git -C repo-git cinnabar data $FILE --metadata
+  copy: a
+  copyrev: b789fdd96dc2f3bd229c1dd8eedf0fc60e2b68e3
+  $ git -C repo-git cinnabar data $FILE --copy
+  b789fdd96dc2f3bd229c1dd8eedf0fc60e2b68e3 a
+
+Files without metadata have none to show.
+
+  $ git -C repo-git cinnabar data b789fdd96dc2f3bd229c1dd8eedf0fc60e2b68e3 --metadata
+  $ git -C repo-git cinnabar data b789fdd96dc2f3bd229c1dd8eedf0fc60e2b68e3 --content
+  a
+  $ git -C repo-git cinnabar data b789fdd96dc2f3bd229c1dd8eedf0fc60e2b68e3 --copy
+
+The files listed in a changeset can be shown along with how they were changed,
+compared to the first parent.
+
+  $ cd repo
+  $ echo aa > a
+  $ hg rm c
+  $ echo d > d
+  $ hg add d
+  $ hg commit -q -m d -u nobody -d "3 0"
+  $ NODE=$(hg log -T '{node}' -r .)
+  $ cd ..
+  $ git -C repo-git cinnabar fetch hg::$REPO $NODE > /dev/null 2>&1
+
+  $ git -C repo-git cinnabar data -c $NODE --files
+  M a
+  D c
+  A d
+  $ git -C repo-git cinnabar data -c f92470d7f6966a39dfbced6a525fe81ebf5c37b9 --files
+  A a
+
+The manifest of a changeset can be shown directly, and is the same as the one
+the changeset refers to.
+
+  $ git -C repo-git cinnabar data -c $NODE --manifest > manifest
+  $ git -C repo-git cinnabar data -m $(git -C repo-git cinnabar data -c $NODE | head -n 1) | cmp - manifest
+  $ tr '\0' ' ' < manifest | cut -d ' ' -f 1
+  a
+  d
+  $ hg -R repo manifest -r $NODE
+  a
+  d
+
+The changes a changeset made to the manifest of its first parent can be shown
+too, as new and old file revisions, the null revision standing for a file that
+doesn't exist on that side. Root changesets are compared to an empty manifest.
+
+  $ git -C repo-git cinnabar data -c f92470d7f6966a39dfbced6a525fe81ebf5c37b9 --manifest-delta
+  b789fdd96dc2f3bd229c1dd8eedf0fc60e2b68e3 0000000000000000000000000000000000000000 a
+  $ git -C repo-git cinnabar data -c $NODE --manifest-delta | sed 's/0\{40\}/null/g;s/[0-9a-f]\{40\}/X/g'
+  X X a
+  null X c
+  X null d
+  $ git -C repo-git cinnabar data -c $NODE --manifest-delta | grep ' d$' | cut -c 1-40 > delta
+  $ hg -R repo manifest --debug -r $NODE | grep ' d$' | cut -c 1-40 | cmp - delta
@@ -0,0 +1,35 @@
+  $ PATH=$TESTDIR/..:$PATH
+
+Create a bundle with a file revision whose metadata is malformed. Mercurial
+itself wouldn't create such a file revision.
+
+  $ python3 $TESTDIR/mkbundle.py bundle.hg foo='\x01\nbogus\n\x01\nfoo\n'
+  2f8baa4561c95c77e80ab11cdfc6a799ca6be45a
+  3b3d08c17023dc8204d9e41ff3a43734dddfd8f9 foo
+
+With the strict-file-metadata check, importing the file revision is fatal.
+
+  $ git init -q repo-strict
+  $ GIT_CINNABAR_CHECK=strict-file-metadata git -C repo-strict cinnabar unbundle $(pwd)/bundle.hg 2>&1 | grep -o '^fatal: .*'
+  fatal: Malformed metadata for file 3b3d08c17023dc8204d9e41ff3a43734dddfd8f9: malformed metadata line #0
+  $ git -C repo-strict cinnabar hg2git 2f8baa4561c95c77e80ab11cdfc6a799ca6be45a
+  0000000000000000000000000000000000000000
+
+Otherwise, it is only a warning, and the file is imported as is.
+
+  $ git init -q repo
+  $ git -C repo cinnabar unbundle $(pwd)/bundle.hg 2>&1 | grep -o 'WARNING .*'
+  WARNING Malformed metadata for file 3b3d08c17023dc8204d9e41ff3a43734dddfd8f9: malformed metadata line #0
+  $ git -C repo cinnabar data 3b3d08c17023dc8204d9e41ff3a43734dddfd8f9
+  \x01 (esc)
+  bogus
+  \x01 (esc)
+  foo
+
+A full fsck reports the malformed metadata.
+
+  $ git -C repo cinnabar fsck --full 2>&1 | grep -A 1 '^Inconsistent metadata'
+  Inconsistent metadata for file foo
+    revision 3b3d08c17023dc8204d9e41ff3a43734dddfd8f9: malformed metadata line #0
+  $ git -C repo cinnabar fsck --full > /dev/null 2>&1
+  [1]
@@ -0,0 +1,45 @@
+  $ PATH=$TESTDIR/..:$PATH
+
+Test repository setup.
+
+  $ n=0
+  $ create() {
+  >   echo $1 > $1
+  >   hg add $1
+  >   hg commit -q -m $1 -u nobody -d "$n 0"
+  >   n=$(expr $n + 1)
+  > }
+
+  $ hg init repo
+  $ REPO=$(pwd)/repo
+
+  $ cd repo
+  $ for f in a b; do create $f; done
+  $ cd ..
+
+  $ git clone -q hg::$REPO repo-git
+
+Each changeset is exported in its own patch file, parents first.
+
+  $ git -C repo-git cinnabar format-patch -o $(pwd)/patches 636e60525868096cbdc961870493510558f41d2f
+  .*/patches/0001-f92470d7f6966a39dfbced6a525fe81ebf5c37b9.patch (re)
+  .*/patches/0002-636e60525868096cbdc961870493510558f41d2f.patch (re)
+  $ ls patches
+  0001-f92470d7f6966a39dfbced6a525fe81ebf5c37b9.patch
+  0002-636e60525868096cbdc961870493510558f41d2f.patch
+  $ grep -h '^Subject:' patches/*
+  Subject: [PATCH] a
+  Subject: [PATCH] b
+  $ grep -h '^+[ab]$' patches/*
+  +a
+  +b
+
+Ancestors of the changesets given with --not are excluded.
+
+  $ git -C repo-git cinnabar format-patch -o $(pwd)/patches-b --not f92470d 636e605
+  .*/patches-b/0001-636e60525868096cbdc961870493510558f41d2f.patch (re)
+
+  $ git -C repo-git cinnabar format-patch -o $(pwd)/patches-c 0123456
+  \r (no-eol) (esc)
+  ERROR Unknown changeset id: 0123456
+  [1]
@@ -0,0 +1,72 @@
+  $ PATH=$TESTDIR/..:$PATH
+
+Test repository setup.
+
+  $ n=0
+  $ create() {
+  >   echo $1 > $1
+  >   hg add $1
+  >   hg commit -q -m $1 -u nobody -d "$n 0"
+  >   n=$(expr $n + 1)
+  > }
+
+  $ hg init repo
+  $ REPO=$(pwd)/repo
+
+  $ cd repo
+  $ for f in a b; do create $f; done
+  $ cd ..
+
+  $ git clone -q hg::$REPO repo-git
+
+Abbreviated commit ids are resolved.
+
+  $ git -C repo-git cinnabar git2hg $(git -C repo-git rev-parse --short=7 HEAD)
+  636e60525868096cbdc961870493510558f41d2f
+
+Prefixes that match no changeset resolve to the null changeset.
+
+  $ git -C repo-git cinnabar git2hg 0000000
+  0000000000000000000000000000000000000000
+
+When git can't be run to disambiguate a prefix, the error is reported.
+
+  $ cd repo-git
+  $ PATH=/nonexistent $TESTDIR/../git-cinnabar git2hg 0000000
+  \r (no-eol) (esc)
+  ERROR failed to run git rev-parse: .* (re)
+  0000000000000000000000000000000000000000
+  $ cd ..
+
+Git blobs can be converted to the file revisions they store. Several file
+revisions can be stored as the same blob, when they only differ by their
+parents or their metadata.
+
+  $ git -C repo-git cinnabar git2hg --file HEAD:a HEAD:b
+  b789fdd96dc2f3bd229c1dd8eedf0fc60e2b68e3
+  [0-9a-f]{40} (re)
+  $ test "$(git -C repo-git cinnabar git2hg --file HEAD:b)" = "$(hg -R repo manifest --debug -r tip | grep ' b$' | cut -c 1-40)"
+
+  $ cd repo
+  $ hg cp -q a c
+  $ hg commit -q -m c -u nobody -d "2 0"
+  $ cd ..
+  $ git -C repo-git fetch -q origin
+  $ git -C repo-git cinnabar git2hg --file origin/branches/default/tip:c | tr ' ' '\n' > files
+  $ wc -l < files
+  2
+  $ grep -c b789fdd96dc2f3bd229c1dd8eedf0fc60e2b68e3 files
+  1
+  $ grep -c "$(hg -R repo manifest --debug -r tip | grep ' c$' | cut -c 1-40)" files
+  1
+
+Blobs that don't store any file revision convert to the null id.
+
+  $ git -C repo-git cinnabar git2hg --file $(echo foo | git -C repo-git hash-object -w --stdin)
+  0000000000000000000000000000000000000000
+
+Blobs can also be given on stdin, with the same results.
+
+  $ FOO=$(echo foo | git -C repo-git hash-object --stdin)
+  $ git -C repo-git cinnabar git2hg --file HEAD:a HEAD:b $FOO > expected
+  $ printf 'HEAD:a HEAD:b\n%s\n' $FOO | git -C repo-git cinnabar git2hg --file --batch | cmp - expected
@@ -0,0 +1,57 @@
+  $ PATH=$TESTDIR/..:$PATH
+
+Test repository setup.
+
+  $ hg init repo
+  $ REPO=$(pwd)/repo
+
+  $ cd repo
+  $ echo a > a
+  $ hg add a
+  $ hg commit -q -m a -u 'nobody <nobody@example.com>' -d "0 0"
+  $ NODE=$(hg log -r . -T '{node}')
+  $ cd ..
+
+Two git commits with the same tree and date as the changeset, that only differ
+by their message, are both candidates to graft the changeset onto.
+
+  $ export GIT_AUTHOR_NAME=nobody GIT_AUTHOR_EMAIL=nobody@example.com GIT_AUTHOR_DATE='0 +0000'
+  $ export GIT_COMMITTER_NAME=nobody GIT_COMMITTER_EMAIL=nobody@example.com GIT_COMMITTER_DATE='0 +0000'
+  $ git init -q repo-git
+  $ echo a > repo-git/a
+  $ git -C repo-git add a
+  $ TREE=$(git -C repo-git write-tree)
+  $ C1=$(echo x | git -C repo-git commit-tree $TREE)
+  $ C2=$(echo y | git -C repo-git commit-tree $TREE)
+  $ git -C repo-git update-ref refs/heads/x $C1
+  $ git -C repo-git update-ref refs/heads/y $C2
+
+Without a hint, grafting fails.
+
+  $ git -C repo-git -c cinnabar.graft=true fetch -q hg::$REPO 2>&1 | grep -o 'Cannot graft .*'
+  Cannot graft [0-9a-f]{40}\. Candidates: [0-9a-f]{40}, [0-9a-f]{40} (re)
+  $ git -C repo-git cinnabar hg2git $NODE
+  0000000000000000000000000000000000000000
+
+Grafting also fails when the hinted commit is not one of the candidates.
+
+  $ echo b > repo-git/a
+  $ git -C repo-git add a
+  $ C3=$(echo z | git -C repo-git commit-tree $(git -C repo-git write-tree))
+  $ echo "$NODE $C3" > hints
+  $ git -C repo-git -c cinnabar.graft=true -c cinnabar.graft-hints=$(pwd)/hints fetch -q hg::$REPO 2>&1 | grep -o 'Cannot graft .*' | sed "s/$NODE/NODE/;s/$C3/C3/"
+  Cannot graft NODE onto C3, which is not a candidate. Candidates: [0-9a-f]{40}, [0-9a-f]{40} (re)
+  $ git -C repo-git cinnabar hg2git $NODE
+  0000000000000000000000000000000000000000
+
+With a hint for one of the candidates, the changeset is grafted onto it. Hints
+can be read from a git blob, as long as it is not under refs/cinnabar/, which
+git-cinnabar manages.
+
+  $ echo "# changeset commit" > hints
+  $ echo "$NODE $C2" >> hints
+  $ BLOB=$(git -C repo-git hash-object -w $(pwd)/hints)
+  $ HINTS_TREE=$(printf '100644 blob %s\thints\n' $BLOB | git -C repo-git mktree)
+  $ git -C repo-git update-ref refs/graft-hints $(echo hints | git -C repo-git commit-tree $HINTS_TREE)
+  $ git -C repo-git -c cinnabar.graft=true -c cinnabar.graft-hints=refs/graft-hints:hints fetch -q hg::$REPO 2> /dev/null
+  $ test "$(git -C repo-git cinnabar hg2git $NODE)" = "$C2"
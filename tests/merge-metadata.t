@@ -0,0 +1,66 @@
+  $ PATH=$TESTDIR/..:$PATH
+
+Test repository setup.
+
+  $ n=0
+  $ create() {
+  >   echo $1 > $1
+  >   hg add $1
+  >   hg commit -q -m $1 -u nobody -d "$n 0"
+  >   n=$(expr $n + 1)
+  > }
+
+  $ hg init repo
+  $ REPO=$(pwd)/repo
+
+  $ cd repo
+  $ for f in a b; do create $f; done
+  $ hg update -q -r 0
+  $ create c
+  $ cd ..
+
+  $ hg -R $REPO log -G --template '{node} {branch} {desc}'
+  @  ae078ae353a9b004afbd6fd6e5e7a5a0a48a4307 default c
+  |
+  | o  636e60525868096cbdc961870493510558f41d2f default b
+  |/
+  o  f92470d7f6966a39dfbced6a525fe81ebf5c37b9 default a
+  
+
+Two repositories each have the metadata for one of the branches.
+
+  $ git init -q repo-b
+  $ git -C repo-b cinnabar fetch hg::$REPO 636e60525868096cbdc961870493510558f41d2f > /dev/null 2>&1
+  $ git init -q repo-c
+  $ git -C repo-c cinnabar fetch hg::$REPO ae078ae353a9b004afbd6fd6e5e7a5a0a48a4307 > /dev/null 2>&1
+  $ git -C repo-c bundle create $(pwd)/repo-c.git refs/cinnabar/metadata 2> /dev/null
+
+  $ git -C repo-b cinnabar hg2git ae078ae353a9b004afbd6fd6e5e7a5a0a48a4307
+  0000000000000000000000000000000000000000
+
+The metadata from the other repository can be merged.
+
+  $ git -C repo-b cinnabar merge-metadata $(pwd)/repo-c.git
+  $ test "$(git -C repo-b cinnabar hg2git ae078ae353a9b004afbd6fd6e5e7a5a0a48a4307)" = "$(git -C repo-c cinnabar hg2git ae078ae353a9b004afbd6fd6e5e7a5a0a48a4307)"
+  $ git -C repo-b cinnabar fsck --full 2> /dev/null
+
+The result is the same as fetching both branches in the same repository.
+
+  $ git init -q repo-full
+  $ git -C repo-full cinnabar fetch hg::$REPO 636e60525868096cbdc961870493510558f41d2f ae078ae353a9b004afbd6fd6e5e7a5a0a48a4307 > /dev/null 2>&1
+  $ test "$(git -C repo-b rev-parse refs/cinnabar/metadata)" = "$(git -C repo-full rev-parse refs/cinnabar/metadata)"
+
+Metadata can also be merged into a repository without metadata.
+
+  $ git init -q repo-new
+  $ git -C repo-new cinnabar merge-metadata $(pwd)/repo-c.git
+  $ test "$(git -C repo-new rev-parse refs/cinnabar/metadata)" = "$(git -C repo-c rev-parse refs/cinnabar/metadata)"
+
+Merging fails when the metadata can't be found.
+
+  $ git -C repo-new cinnabar merge-metadata $(pwd)/repo-c.git foo
+  \r (no-eol) (esc)
+  ERROR Could not find cinnabar metadata
+  \r (no-eol) (esc)
+  ERROR Failed to merge cinnabar metadata
+  [1]
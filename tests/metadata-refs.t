@@ -0,0 +1,102 @@
+  $ PATH=$TESTDIR/..:$PATH
+
+Test repository setup.
+
+  $ n=0
+  $ create() {
+  >   echo $1 > $1
+  >   hg add $1
+  >   hg commit -q -m $1 -u nobody -d "$n 0"
+  >   n=$(expr $n + 1)
+  > }
+
+  $ hg init repo
+  $ REPO=$(pwd)/repo
+
+  $ cd repo
+  $ for f in a b; do create $f; done
+  $ cd ..
+
+  $ git init -q repo-git
+  $ LOG=$(pwd)/check-files.log
+  $ GIT_CINNABAR_LOG="check-files:4>$LOG" git -C repo-git cinnabar fetch hg::$REPO f92470d7f6966a39dfbced6a525fe81ebf5c37b9
+  From hg::.*/metadata-refs.t/repo (re)
+   * branch            hg/revs/f92470d7f6966a39dfbced6a525fe81ebf5c37b9 -> FETCH_HEAD
+  $ grep -o '\[check-files\] .*' $LOG
+  [check-files] 1 out of 1 stored files need checking
+  $ rm $LOG
+
+The files check, the changeset heads cache and the update time are recorded
+for the current metadata.
+
+  $ git -C repo-git for-each-ref --format='%(refname)' refs/cinnabar/
+  refs/cinnabar/changeset-heads
+  refs/cinnabar/files-checked
+  refs/cinnabar/metadata
+  refs/cinnabar/metadata-updated
+  $ METADATA=$(git -C repo-git rev-parse refs/cinnabar/metadata)
+  $ test "$(git -C repo-git rev-parse refs/cinnabar/files-checked)" = "$METADATA"
+  $ test "$(git -C repo-git rev-parse refs/cinnabar/changeset-heads^)" = "$METADATA"
+  $ test "$(git -C repo-git rev-parse refs/cinnabar/metadata-updated^)" = "$METADATA"
+
+  $ NOW=$(date +%s)
+  $ UPDATED=$(git -C repo-git log -1 --format=%ct refs/cinnabar/metadata-updated)
+  $ test $UPDATED -le $NOW && test $UPDATED -gt $(expr $NOW - 600)
+
+Fetching again doesn't change the metadata, so the refs are kept, and the files
+don't need to be checked again.
+
+  $ UPDATED_REF=$(git -C repo-git rev-parse refs/cinnabar/metadata-updated)
+  $ GIT_CINNABAR_LOG="check-files:4>$LOG" git -C repo-git cinnabar fetch hg::$REPO f92470d7f6966a39dfbced6a525fe81ebf5c37b9
+  From hg::.*/metadata-refs.t/repo (re)
+   * branch            hg/revs/f92470d7f6966a39dfbced6a525fe81ebf5c37b9 -> FETCH_HEAD
+  $ grep -o '\[check-files\] .*' $LOG
+  [check-files] Files were already checked for this metadata
+  $ rm $LOG
+
+  $ git -C repo-git for-each-ref --format='%(refname)' refs/cinnabar/
+  refs/cinnabar/changeset-heads
+  refs/cinnabar/files-checked
+  refs/cinnabar/metadata
+  refs/cinnabar/metadata-updated
+  $ test "$(git -C repo-git rev-parse refs/cinnabar/metadata)" = "$METADATA"
+  $ test "$(git -C repo-git rev-parse refs/cinnabar/metadata-updated)" = "$UPDATED_REF"
+
+Fetching a new changeset updates all of them.
+
+  $ GIT_CINNABAR_LOG="check-files:4>$LOG" git -C repo-git cinnabar fetch hg::$REPO 636e60525868096cbdc961870493510558f41d2f
+  From hg::.*/metadata-refs.t/repo (re)
+   * branch            hg/revs/636e60525868096cbdc961870493510558f41d2f -> FETCH_HEAD
+  $ grep -o '\[check-files\] .*' $LOG
+  [check-files] 1 out of 1 stored files need checking
+  $ rm $LOG
+
+  $ NEW_METADATA=$(git -C repo-git rev-parse refs/cinnabar/metadata)
+  $ test "$NEW_METADATA" != "$METADATA"
+  $ test "$(git -C repo-git rev-parse refs/cinnabar/files-checked)" = "$NEW_METADATA"
+  $ test "$(git -C repo-git rev-parse refs/cinnabar/changeset-heads^)" = "$NEW_METADATA"
+  $ test "$(git -C repo-git rev-parse refs/cinnabar/metadata-updated^)" = "$NEW_METADATA"
+
+After a rollback, the changeset heads cache and the update time don't match the
+metadata anymore, and are removed. The files that were checked are still known.
+
+  $ git -C repo-git cinnabar rollback
+  $ git -C repo-git for-each-ref --format='%(refname)' refs/cinnabar/
+  refs/cinnabar/files-checked
+  refs/cinnabar/metadata
+  $ test "$(git -C repo-git rev-parse refs/cinnabar/metadata)" = "$METADATA"
+  $ test "$(git -C repo-git rev-parse refs/cinnabar/files-checked)" = "$NEW_METADATA"
+
+Fetching the same changeset again yields the same metadata, which files were
+already checked.
+
+  $ GIT_CINNABAR_LOG="check-files:4>$LOG" git -C repo-git cinnabar fetch hg::$REPO 636e60525868096cbdc961870493510558f41d2f
+  From hg::.*/metadata-refs.t/repo (re)
+   * branch            hg/revs/636e60525868096cbdc961870493510558f41d2f -> FETCH_HEAD
+  $ grep -o '\[check-files\] .*' $LOG
+  [check-files] Files were already checked for this metadata
+  $ rm $LOG
+
+  $ test "$(git -C repo-git rev-parse refs/cinnabar/metadata)" = "$NEW_METADATA"
+  $ test "$(git -C repo-git rev-parse refs/cinnabar/changeset-heads^)" = "$NEW_METADATA"
+  $ test "$(git -C repo-git rev-parse refs/cinnabar/metadata-updated^)" = "$NEW_METADATA"
@@ -0,0 +1,49 @@
+  $ PATH=$TESTDIR/..:$PATH
+
+Test repository setup.
+
+  $ n=0
+  $ create() {
+  >   echo $1 > $1
+  >   hg add $1
+  >   hg commit -q -m $1 -u nobody -d "$n 0"
+  >   n=$(expr $n + 1)
+  > }
+
+  $ hg init repo
+  $ REPO=$(pwd)/repo
+
+  $ cd repo
+  $ for f in a b; do create $f; done
+  $ hg bundle -q -t none-v1 -a ../full.hg
+  $ cd ..
+
+Create a bundle whose manifest group is empty, so that its changesets reference
+manifests that are nowhere to be found.
+
+  $ python3 - full.hg no-manifest.hg <<'PYEOF'
+  > import struct, sys
+  > data = open(sys.argv[1], 'rb').read()
+  > assert data[:6] == b'HG10UN'
+  > def skip_group(pos):
+  >     while True:
+  >         (length,) = struct.unpack('>l', data[pos:pos + 4])
+  >         if length <= 4:
+  >             return pos + 4
+  >         pos += length
+  > changesets_end = skip_group(6)
+  > manifests_end = skip_group(changesets_end)
+  > with open(sys.argv[2], 'wb') as out:
+  >     out.write(data[:changesets_end])
+  >     out.write(struct.pack('>l', 0))
+  >     out.write(data[manifests_end:])
+  > PYEOF
+
+  $ git init -q repo-git
+  $ git -C repo-git cinnabar unbundle $(pwd)/no-manifest.hg 2>&1 | grep '^fatal:'
+  fatal: Changeset f92470d7f6966a39dfbced6a525fe81ebf5c37b9 references manifest a0c8bcbbb45c63b90b70ad007bf38961f64f2af0, which is neither in the changegroup nor already stored
+
+The full bundle is fine.
+
+  $ git -C repo-git cinnabar unbundle $(pwd)/full.hg
+  $ git -C repo-git cinnabar hg2git f92470d7f6966a39dfbced6a525fe81ebf5c37b9 > /dev/null
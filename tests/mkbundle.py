@@ -0,0 +1,86 @@
+# This Source Code Form is subject to the terms of the Mozilla Public
+# License, v. 2.0. If a copy of the MPL was not distributed with this
+# file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+# Creates a HG10UN bundle with a single root changeset, from the given file
+# texts and changeset text, which Mercurial itself might not create.
+# Arguments accept python escape sequences, such as \n or \x01.
+#
+# Prints the changeset node, followed by the node and name of each file.
+
+import argparse
+import codecs
+import hashlib
+import struct
+
+NULL = b'\0' * 20
+
+
+def unescape(s):
+    return codecs.escape_decode(s.encode())[0]
+
+
+def node(text, p1=NULL, p2=NULL):
+    return hashlib.sha1(min(p1, p2) + max(p1, p2) + text).digest()
+
+
+def chunk(data):
+    return struct.pack('>l', len(data) + 4) + data
+
+
+def rev(n, text, cs):
+    # A single delta against the empty text, since there are no parents.
+    delta = struct.pack('>lll', 0, 0, len(text)) + text
+    return chunk(n + NULL + NULL + cs + delta)
+
+
+def file_arg(s):
+    name, text = s.split('=', 1)
+    return name.encode(), unescape(text)
+
+
+def main():
+    parser = argparse.ArgumentParser()
+    parser.add_argument('bundle', help='path of the bundle to create')
+    parser.add_argument('files', nargs='+', type=file_arg, metavar='NAME=TEXT',
+                        help='file in the changeset, and its text')
+    parser.add_argument('--changeset', type=unescape, metavar='TEXT',
+                        help='changeset text following the manifest node. '
+                             'Defaults to a changeset by nobody touching all '
+                             'the files, in order')
+    parser.add_argument('--stored', type=file_arg, action='append',
+                        default=[], metavar='NAME=TEXT',
+                        help='store the given text for the file, without '
+                             'changing its node')
+    args = parser.parse_args()
+
+    files = dict(args.files)
+    nodes = {f: node(t) for f, t in files.items()}
+    files.update(args.stored)
+    manifest_text = b''.join(f + b'\0' + nodes[f].hex().encode() + b'\n'
+                             for f in sorted(files))
+    manifest_node = node(manifest_text)
+    changeset_text = args.changeset
+    if changeset_text is None:
+        changeset_text = (b'nobody\n0 0\n' + b'\n'.join(sorted(files)) +
+                          b'\n\nfoo')
+    changeset_text = manifest_node.hex().encode() + b'\n' + changeset_text
+    changeset_node = node(changeset_text)
+    with open(args.bundle, 'wb') as out:
+        out.write(b'HG10UN')
+        out.write(rev(changeset_node, changeset_text, changeset_node))
+        out.write(struct.pack('>l', 0))
+        out.write(rev(manifest_node, manifest_text, changeset_node))
+        out.write(struct.pack('>l', 0))
+        for f in sorted(files):
+            out.write(chunk(f))
+            out.write(rev(nodes[f], files[f], changeset_node))
+            out.write(struct.pack('>l', 0))
+        out.write(struct.pack('>l', 0))
+    print(changeset_node.hex())
+    for f in sorted(files):
+        print(nodes[f].hex(), f.decode())
+
+
+if __name__ == '__main__':
+    main()
@@ -0,0 +1,44 @@
+  $ PATH=$TESTDIR/..:$PATH
+
+Test repository setup.
+
+  $ n=0
+  $ create() {
+  >   echo $1 > $1
+  >   hg add $1
+  >   hg commit -q -m $1 -u nobody -d "$n 0"
+  >   n=$(expr $n + 1)
+  > }
+
+  $ hg init repo
+  $ REPO=$(pwd)/repo
+
+  $ cd repo
+  $ for f in a b; do create $f; done
+  $ hg update -q -r 0
+  $ create c
+  $ hg bundle -q -a $(pwd)/../repo.hg
+  $ cd ..
+
+Importing the same changegroup in two different repositories yields the same
+metadata.
+
+  $ git init -q repo-git-1
+  $ git -C repo-git-1 cinnabar unbundle $(pwd)/repo.hg > /dev/null 2>&1
+  $ git init -q repo-git-2
+  $ git -C repo-git-2 cinnabar unbundle $(pwd)/repo.hg > /dev/null 2>&1
+  $ METADATA=$(git -C repo-git-1 rev-parse refs/cinnabar/metadata)
+  $ test "$(git -C repo-git-2 rev-parse refs/cinnabar/metadata)" = "$METADATA"
+
+Importing the heads in a different order yields the same changesets, manifests
+and notes metadata, although the metadata commits themselves differ, since the
+last one has the first one as parent.
+
+  $ git init -q repo-git-3
+  $ git -C repo-git-3 cinnabar fetch hg::$REPO ae078ae353a9b004afbd6fd6e5e7a5a0a48a4307 > /dev/null 2>&1
+  $ git -C repo-git-3 cinnabar fetch hg::$REPO 636e60525868096cbdc961870493510558f41d2f > /dev/null 2>&1
+  $ git -C repo-git-1 log -1 --format=%P refs/cinnabar/metadata | cut -d ' ' -f 1-5 > parents-1
+  $ git -C repo-git-3 log -1 --format=%P refs/cinnabar/metadata | cut -d ' ' -f 1-5 > parents-3
+  $ cmp parents-1 parents-3
+  $ test "$(git -C repo-git-3 rev-parse refs/cinnabar/metadata^{tree})" = "$(git -C repo-git-1 rev-parse refs/cinnabar/metadata^{tree})"
+  $ test "$(git -C repo-git-3 rev-parse refs/cinnabar/metadata)" != "$METADATA"
@@ -0,0 +1,47 @@
+  $ PATH=$TESTDIR/..:$PATH
+
+Test repository setup.
+
+  $ n=0
+  $ create() {
+  >   echo $1 > $1
+  >   hg add $1
+  >   hg commit -q -m $1 -u nobody -d "$n 0"
+  >   n=$(expr $n + 1)
+  > }
+
+  $ hg init repo
+  $ REPO=$(pwd)/repo
+
+  $ cd repo
+  $ create a
+  $ cd ..
+
+  $ git clone -q hg::$REPO repo-git
+  $ OLD=$(git -C repo-git rev-parse refs/cinnabar/metadata)
+
+  $ cd repo
+  $ create b
+  $ cd ..
+  $ git -C repo-git fetch -q origin
+
+The remote helper picks up changes to the metadata made by something else
+between two commands. Here, the metadata is reset to before changeset b was
+imported, which the second list shows.
+
+  $ mkfifo helper-in
+  $ (cd repo-git && git-remote-hg origin $REPO < ../helper-in > ../helper-out 2> /dev/null) &
+  $ exec 3> helper-in
+  $ printf 'list\n' >&3
+  $ while ! grep -q '^$' helper-out 2> /dev/null; do sleep 0.1; done
+  $ git -C repo-git update-ref refs/cinnabar/metadata $OLD
+  $ printf 'list\n' >&3
+  $ exec 3>&-
+  $ wait
+  $ cat helper-out
+  @refs/heads/branches/default/tip HEAD
+  [0-9a-f]{40} refs/heads/branches/default/tip (re)
+  
+  @refs/heads/branches/default/tip HEAD
+  ? refs/heads/branches/default/tip
+  
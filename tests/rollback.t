@@ -70,8 +70,11 @@ metadata history.
   9134dcc9628afe079a8a61e06f1e49a36a983cc4
 
   $ git -C repo-git for-each-ref refs/cinnabar/ refs/notes/
+  [0-9a-f]{40} commit\trefs/cinnabar/changeset-heads (re)
   ee1547daada51509736d29942d8ad9cdd53e5500 commit	refs/cinnabar/checked
+  fdc5127c26b6de6ec365bc18e9a4ae2ef2f35c08 commit	refs/cinnabar/files-checked
   fdc5127c26b6de6ec365bc18e9a4ae2ef2f35c08 commit	refs/cinnabar/metadata
+  [0-9a-f]{40} commit\trefs/cinnabar/metadata-updated (re)
   0d790d01720127d15c119268277eda391270b588 commit	refs/notes/cinnabar
 
 Fake fsck breakage
@@ -87,8 +90,11 @@ Fake fsck breakage
 
   $ git -C repo-git for-each-ref refs/cinnabar/ refs/notes/
   fdc5127c26b6de6ec365bc18e9a4ae2ef2f35c08 commit	refs/cinnabar/broken
+  [0-9a-f]{40} commit\trefs/cinnabar/changeset-heads (re)
   ee1547daada51509736d29942d8ad9cdd53e5500 commit	refs/cinnabar/checked
+  fdc5127c26b6de6ec365bc18e9a4ae2ef2f35c08 commit	refs/cinnabar/files-checked
   fdc5127c26b6de6ec365bc18e9a4ae2ef2f35c08 commit	refs/cinnabar/metadata
+  [0-9a-f]{40} commit\trefs/cinnabar/metadata-updated (re)
   0d790d01720127d15c119268277eda391270b588 commit	refs/notes/cinnabar
 
 Rollback to the previous metadata. Its status is not broken but unknown.
@@ -104,6 +110,7 @@ Rollback to the previous metadata. Its status is not broken but unknown.
   $ git -C repo-git for-each-ref refs/cinnabar/ refs/notes/
   fdc5127c26b6de6ec365bc18e9a4ae2ef2f35c08 commit	refs/cinnabar/broken
   ee1547daada51509736d29942d8ad9cdd53e5500 commit	refs/cinnabar/checked
+  fdc5127c26b6de6ec365bc18e9a4ae2ef2f35c08 commit	refs/cinnabar/files-checked
   1533f7bbc5d7bd3e420cd927b890097cf660531e commit	refs/cinnabar/metadata
   78ae75b918bc679865b2b566d64c81864d8ce7e4 commit	refs/notes/cinnabar
 
@@ -118,6 +125,7 @@ Rollback to the last known good
   $ git -C repo-git for-each-ref refs/cinnabar/ refs/notes/
   fdc5127c26b6de6ec365bc18e9a4ae2ef2f35c08 commit	refs/cinnabar/broken
   ee1547daada51509736d29942d8ad9cdd53e5500 commit	refs/cinnabar/checked
+  fdc5127c26b6de6ec365bc18e9a4ae2ef2f35c08 commit	refs/cinnabar/files-checked
   ee1547daada51509736d29942d8ad9cdd53e5500 commit	refs/cinnabar/metadata
   abbd4d8876d12871c52111ef763728cc70b60c20 commit	refs/notes/cinnabar
 
@@ -132,6 +140,7 @@ considered checked.
   $ git -C repo-git for-each-ref refs/cinnabar/ refs/notes/
   fdc5127c26b6de6ec365bc18e9a4ae2ef2f35c08 commit	refs/cinnabar/broken
   9134dcc9628afe079a8a61e06f1e49a36a983cc4 commit	refs/cinnabar/checked
+  fdc5127c26b6de6ec365bc18e9a4ae2ef2f35c08 commit	refs/cinnabar/files-checked
   9134dcc9628afe079a8a61e06f1e49a36a983cc4 commit	refs/cinnabar/metadata
   522a8fcad148fe794046af5769734cdd44f3ebc4 commit	refs/notes/cinnabar
 
@@ -154,6 +163,7 @@ state of ee1547d.
   $ git -C repo-git for-each-ref refs/cinnabar/ refs/notes/
   fdc5127c26b6de6ec365bc18e9a4ae2ef2f35c08 commit	refs/cinnabar/broken
   9134dcc9628afe079a8a61e06f1e49a36a983cc4 commit	refs/cinnabar/checked
+  fdc5127c26b6de6ec365bc18e9a4ae2ef2f35c08 commit	refs/cinnabar/files-checked
   fdc5127c26b6de6ec365bc18e9a4ae2ef2f35c08 commit	refs/cinnabar/metadata
   0d790d01720127d15c119268277eda391270b588 commit	refs/notes/cinnabar
 
@@ -0,0 +1,92 @@
+  $ PATH=$TESTDIR/..:$PATH
+
+Test repository setup.
+
+  $ n=0
+  $ create() {
+  >   echo $1 > $1
+  >   hg add $1
+  >   hg commit -q -m $1 -u nobody -d "$n 0"
+  >   n=$(expr $n + 1)
+  > }
+
+  $ hg init repo
+  $ REPO=$(pwd)/repo
+
+  $ cd repo
+  $ for f in a b; do create $f; done
+  $ echo a2 > a
+  $ hg commit -q -m a2 -u nobody -d "$n 0"
+  $ cd ..
+
+  $ git clone -q hg::$REPO repo-git
+
+Three changesets and three file revisions were imported: two of a, and one of
+b.
+
+  $ git -C repo-git cinnabar stats
+  changesets: 3
+  file revisions: 3
+
+Changesets whose git commits have the same tree can be listed. Here, removing
+the b file goes back to the tree of the first changeset.
+
+  $ cd repo
+  $ hg update -q -r 1
+  $ hg rm -q b
+  $ hg commit -q -m c -u nobody -d "3 0"
+  $ cd ..
+  $ git -C repo-git cinnabar fetch hg::$REPO 7579f63e0812492bf7e5cfd783979142395c530f > /dev/null 2>&1
+  $ git -C repo-git cinnabar stats
+  changesets: 4
+  file revisions: 3
+  $ git -C repo-git cinnabar stats --shared-trees
+  aaff74984cccd156a469afa7d9ab10e4777beb24 7579f63e0812492bf7e5cfd783979142395c530f f92470d7f6966a39dfbced6a525fe81ebf5c37b9
+
+All the manifests can be listed, parents first.
+
+  $ hg -R repo log -r 'sort(all(), rev)' -T '{node}\n' > changesets
+  $ for cs in $(cat changesets); do git -C repo-git cinnabar data -c $cs | head -n 1; done > manifests
+  $ git -C repo-git cinnabar stats --manifests > manifests-topo
+  $ wc -l < manifests-topo
+  4
+  $ head -n 2 manifests > expected
+  $ head -n 2 manifests-topo | cmp - expected
+  $ sort manifests > expected
+  $ sort manifests-topo | cmp - expected
+
+The changesets in a range can be counted. The a2 changeset is on one side of
+the history, and the c changeset on the other, both sharing the first two
+changesets.
+
+  $ A2=$(hg -R repo log -r 2 -T '{node}')
+  $ C=$(hg -R repo log -r 3 -T '{node}')
+  $ git -C repo-git cinnabar stats --head $C
+  changesets: 3
+  $ git -C repo-git cinnabar stats --head $A2 --head $C
+  changesets: 4
+  $ git -C repo-git cinnabar stats --head $A2 --common $C
+  changesets: 1
+  $ git -C repo-git cinnabar stats --head $C --common $C
+  changesets: 0
+  $ git -C repo-git cinnabar stats --head 0123456789abcdef
+  \r (no-eol) (esc)
+  ERROR Unknown changeset id: 0123456789abcdef
+  [1]
+
+Changesets are numbered in the order of their history, parents first. While
+similar, the numbers are not necessarily Mercurial's revision numbers. The
+git commit for the changeset and the number of changesets descending from it
+are shown too.
+
+  $ git -C repo-git cinnabar stats --changeset $(hg -R repo log -r 0 -T '{node}') | tail -n 2
+  rev: 0
+  descendants: 3
+  $ git -C repo-git cinnabar stats --changeset $(hg -R repo log -r 1 -T '{node}') | tail -n 2
+  rev: 1
+  descendants: 2
+  $ git -C repo-git cinnabar stats --changeset $C | tail -n 2
+  rev: [23] (re)
+  descendants: 0
+  $ git -C repo-git cinnabar stats --changeset $C | head -n 1 > commit
+  $ echo "commit: $(git -C repo-git cinnabar hg2git $C)" | cmp - commit
@@ -0,0 +1,64 @@
+  $ PATH=$TESTDIR/..:$PATH
+
+Test repository setup.
+
+  $ n=0
+  $ create() {
+  >   echo $1 > $1
+  >   hg add $1
+  >   hg commit -q -m $1 -u nobody -d "$n 0"
+  >   n=$(expr $n + 1)
+  > }
+
+  $ hg init repo
+  $ REPO=$(pwd)/repo
+
+  $ cd repo
+  $ for f in a b; do create $f; done
+  $ cd ..
+
+  $ git init -q repo-git
+  $ git -C repo-git cinnabar fetch hg::$REPO f92470d7f6966a39dfbced6a525fe81ebf5c37b9
+  From hg::.*/stored-bundle.t/repo (re)
+   * branch            hg/revs/f92470d7f6966a39dfbced6a525fe81ebf5c37b9 -> FETCH_HEAD
+
+There is no stored bundle yet.
+
+  $ git -C repo-git cinnabar bundle --stored $(pwd)/stored.hg
+  \r (no-eol) (esc)
+  ERROR No bundle is stored along the metadata
+  [1]
+  $ test -e stored.hg
+  [1]
+
+With the unbundler check, changegroups applied on top of existing metadata are
+kept in a bundle along the changesets metadata.
+
+  $ GIT_CINNABAR_CHECK=unbundler git -C repo-git cinnabar fetch hg::$REPO 636e60525868096cbdc961870493510558f41d2f
+  From hg::.*/stored-bundle.t/repo (re)
+   * branch            hg/revs/636e60525868096cbdc961870493510558f41d2f -> FETCH_HEAD
+  $ git -C repo-git cat-file -t refs/cinnabar/metadata^:bundle
+  blob
+
+The stored bundle can be extracted.
+
+  $ git -C repo-git cinnabar bundle --stored $(pwd)/stored.hg
+  $ head -c 4 stored.hg
+  HG20 (no-eol)
+
+The streamed bundle is the same as the blob read whole.
+
+  $ git -C repo-git cat-file blob refs/cinnabar/metadata^:bundle > read.hg
+  $ cmp read.hg stored.hg
+
+Fsck checks the stored bundle.
+
+  $ git -C repo-git cinnabar fsck --force 2> /dev/null
+
+Storing the same bundle again, after a rollback, reuses the blob that is
+already in a pack.
+
+  $ git -C repo-git cinnabar rollback
+  $ GIT_CINNABAR_CHECK=unbundler git -C repo-git cinnabar fetch hg::$REPO 636e60525868096cbdc961870493510558f41d2f > /dev/null 2>&1
+  $ git -C repo-git cat-file blob refs/cinnabar/metadata^:bundle | cmp - read.hg
+  $ git -C repo-git cinnabar fsck --force 2> /dev/null
@@ -0,0 +1,77 @@
+  $ PATH=$TESTDIR/..:$PATH
+
+Test repository setup.
+
+  $ n=0
+  $ create() {
+  >   echo $1 > $1
+  >   hg add $1
+  >   hg commit -q -m $1 -u nobody -d "$n 0"
+  >   n=$(expr $n + 1)
+  > }
+
+  $ hg init repo
+  $ REPO=$(pwd)/repo
+
+  $ cd repo
+  $ for f in a b; do create $f; done
+  $ hg tag -r 0 -u nobody -d "2 0" foo
+  $ hg tag -r 1 -u nobody -d "3 0" bar
+  $ hg tag --remove -u nobody -d "4 0" foo
+  $ hg tag -r 0 -u nobody -d "5 0" qux
+  $ cd ..
+
+  $ git clone -q hg::$REPO repo-git
+
+Tags are listed with the changeset they point to and the corresponding git
+commit. Removed tags are not listed.
+
+  $ git -C repo-git cinnabar tags
+  636e60525868096cbdc961870493510558f41d2f [0-9a-f]{40} bar (re)
+  f92470d7f6966a39dfbced6a525fe81ebf5c37b9 [0-9a-f]{40} qux (re)
+  $ test "$(git -C repo-git cinnabar tags bar | cut -d ' ' -f 2)" = "$(git -C repo-git cinnabar hg2git 636e60525868096cbdc961870493510558f41d2f)"
+
+Tags can be looked up by name.
+
+  $ git -C repo-git cinnabar tags qux
+  f92470d7f6966a39dfbced6a525fe81ebf5c37b9 [0-9a-f]{40} qux (re)
+
+  $ git -C repo-git cinnabar tags foo
+  \r (no-eol) (esc)
+  ERROR Unknown tag: foo
+  [1]
+
+Local tags from `cinnabar.localtags` are listed along with the global tags, and
+marked as such. Global tags take precedence over local tags of the same name.
+
+  $ cat > localtags <<EOF
+  > f92470d7f6966a39dfbced6a525fe81ebf5c37b9 loc
+  > f92470d7f6966a39dfbced6a525fe81ebf5c37b9 bar
+  > EOF
+  $ git -C repo-git -c cinnabar.localtags=$(pwd)/localtags cinnabar tags
+  636e60525868096cbdc961870493510558f41d2f [0-9a-f]{40} bar (re)
+  f92470d7f6966a39dfbced6a525fe81ebf5c37b9 [0-9a-f]{40} loc \(local\) (re)
+  f92470d7f6966a39dfbced6a525fe81ebf5c37b9 [0-9a-f]{40} qux (re)
+  $ git -C repo-git -c cinnabar.localtags=$(pwd)/localtags cinnabar tags loc
+  f92470d7f6966a39dfbced6a525fe81ebf5c37b9 [0-9a-f]{40} loc \(local\) (re)
+
+With --listkeys, the global tags are given in the listkeys format, without the
+local tags.
+
+  $ git -C repo-git -c cinnabar.localtags=$(pwd)/localtags cinnabar tags --listkeys | sort
+  bar\t636e60525868096cbdc961870493510558f41d2f (esc)
+  qux\tf92470d7f6966a39dfbced6a525fe81ebf5c37b9 (esc)
+
+With --json, tags are given as a JSON array. Tag names that are not valid UTF-8
+are given as hexadecimal bytes in a separate field.
+
+  $ printf 'f92470d7f6966a39dfbced6a525fe81ebf5c37b9 caf\351\n' >> localtags
+  $ git -C repo-git -c cinnabar.localtags=$(pwd)/localtags cinnabar tags --json | tr '{' '\n'
+  [
+  "tag":"bar","hg":"636e60525868096cbdc961870493510558f41d2f","git":"[0-9a-f]{40}","local":false}, (re)
+  "tag_hex":"636166e9","hg":"f92470d7f6966a39dfbced6a525fe81ebf5c37b9","git":"[0-9a-f]{40}","local":true}, (re)
+  "tag":"loc","hg":"f92470d7f6966a39dfbced6a525fe81ebf5c37b9","git":"[0-9a-f]{40}","local":true}, (re)
+  "tag":"qux","hg":"f92470d7f6966a39dfbced6a525fe81ebf5c37b9","git":"[0-9a-f]{40}","local":false}] (re)
+  $ git -C repo-git cinnabar tags --json qux | tr '{' '\n'
+  [
+  "tag":"qux","hg":"f92470d7f6966a39dfbced6a525fe81ebf5c37b9","git":"[0-9a-f]{40}","local":false}] (re)
@@ -0,0 +1,38 @@
+  $ PATH=$TESTDIR/..:$PATH
+
+Test repository setup, with several revisions of several files, some of which
+have file metadata.
+
+  $ hg init repo
+  $ REPO=$(pwd)/repo
+
+  $ cd repo
+  $ for n in 0 1 2 3 4 5; do
+  >   for f in a b c; do
+  >     seq 1 $n > $f
+  >     echo $f >> $f
+  >   done
+  >   hg commit -q -A -m $n -u nobody -d "$n 0"
+  > done
+  $ hg cp a d
+  $ hg commit -q -m copy -u nobody -d "6 0"
+  $ echo d >> d
+  $ hg commit -q -m d -u nobody -d "7 0"
+  $ cd ..
+
+Importing with the file deltas applied on worker threads gives the same
+metadata as importing serially.
+
+  $ git clone -q hg::$REPO repo-serial
+  $ GIT_CINNABAR_CHECK=threaded-files git clone -q hg::$REPO repo-threaded
+
+  $ test "$(git -C repo-serial rev-parse refs/cinnabar/metadata)" = "$(git -C repo-threaded rev-parse refs/cinnabar/metadata)"
+  $ git -C repo-threaded cinnabar fsck --full 2> /dev/null
+
+Batching the note updates for file revisions gives the same metadata as well,
+whether the file deltas are applied serially or on worker threads.
+
+  $ git -c cinnabar.set-batch-size=4 clone -q hg::$REPO repo-batched
+  $ test "$(git -C repo-serial rev-parse refs/cinnabar/metadata)" = "$(git -C repo-batched rev-parse refs/cinnabar/metadata)"
+  $ GIT_CINNABAR_CHECK=threaded-files git -c cinnabar.set-batch-size=4 clone -q hg::$REPO repo-threaded-batched
+  $ test "$(git -C repo-serial rev-parse refs/cinnabar/metadata)" = "$(git -C repo-threaded-batched rev-parse refs/cinnabar/metadata)"
@@ -0,0 +1,58 @@
+  $ PATH=$TESTDIR/..:$PATH
+
+Test repository setup.
+
+  $ n=0
+  $ create() {
+  >   echo $1 > $1
+  >   hg add $1
+  >   hg commit -q -m $1 -u nobody -d "$n 0"
+  >   n=$(expr $n + 1)
+  > }
+
+  $ hg init repo
+  $ REPO=$(pwd)/repo
+
+  $ cd repo
+  $ for f in a b; do create $f; done
+  $ cd ..
+
+  $ git clone -q hg::$REPO repo-git
+  $ git -C repo-git cinnabar fsck --full 2> /dev/null
+
+Replace the changeset metadata for the head with a truncated copy.
+
+  $ HEAD=$(git -C repo-git cinnabar hg2git 636e60525868096cbdc961870493510558f41d2f)
+  $ GIT2HG=$(git -C repo-git rev-parse refs/cinnabar/metadata^4)
+  $ git -C repo-git cat-file blob $GIT2HG:$HEAD | head -n 1
+  changeset 636e60525868096cbdc961870493510558f41d2f
+  $ TRUNCATED=$(git -C repo-git cat-file blob $GIT2HG:$HEAD | head -c 20 | git -C repo-git hash-object -w --stdin)
+  $ TREE=$(git -C repo-git ls-tree $GIT2HG | sed "s/ [0-9a-f]*\t$HEAD$/ $TRUNCATED\t$HEAD/" | git -C repo-git mktree)
+  $ NEW_GIT2HG=$(git -C repo-git commit-tree $TREE -p $GIT2HG -m "")
+  $ METADATA=$(git -C repo-git cat-file commit refs/cinnabar/metadata | sed "s/^parent $GIT2HG$/parent $NEW_GIT2HG/" | git -C repo-git hash-object -t commit -w --stdin)
+  $ ORIG_METADATA=$(git -C repo-git rev-parse refs/cinnabar/metadata)
+  $ git -C repo-git update-ref refs/cinnabar/metadata $METADATA
+
+A full fsck reports the metadata that can't be parsed.
+
+  $ git -C repo-git cinnabar fsck --full --force 2>&1 | grep -o "Cannot parse note for git commit: .*" | sed "s/$HEAD/HEAD/"
+  Cannot parse note for git commit: HEAD
+
+Showing the patch of the changeset fails instead of showing nothing.
+
+  $ git -C repo-git cinnabar data -c 636e60525868096cbdc961870493510558f41d2f --patch
+  \r (no-eol) (esc)
+  ERROR Cannot read changeset 636e60525868096cbdc961870493510558f41d2f: malformed changeset metadata: invalid changeset metadata
+  [1]
+
+Notes for commits that are not reachable from the metadata are checked too.
+
+  $ git -C repo-git update-ref refs/cinnabar/metadata $ORIG_METADATA
+  $ OTHER=$(git -C repo-git commit-tree $(git -C repo-git rev-parse $HEAD^{tree}) -m other)
+  $ GARBAGE=$(echo garbage | git -C repo-git hash-object -w --stdin)
+  $ TREE=$( (git -C repo-git ls-tree $GIT2HG; printf "100644 blob $GARBAGE\t$OTHER\n") | git -C repo-git mktree)
+  $ NEW_GIT2HG=$(git -C repo-git commit-tree $TREE -p $GIT2HG -m "")
+  $ METADATA=$(git -C repo-git cat-file commit refs/cinnabar/metadata | sed "s/^parent $GIT2HG$/parent $NEW_GIT2HG/" | git -C repo-git hash-object -t commit -w --stdin)
+  $ git -C repo-git update-ref refs/cinnabar/metadata $METADATA
+  $ git -C repo-git cinnabar fsck --full --force 2>&1 | grep -o "Cannot parse note for git commit: .*" | sed "s/$OTHER/OTHER/"
+  Cannot parse note for git commit: OTHER
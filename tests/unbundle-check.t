@@ -0,0 +1,37 @@
+  $ PATH=$TESTDIR/..:$PATH
+
+Create a bundle, optionally with a file revision whose content doesn't match its
+node.
+
+  $ CS=$(python3 $TESTDIR/mkbundle.py good.hg foo='foo\n' | head -n 1)
+  $ python3 $TESTDIR/mkbundle.py bad.hg foo='foo\n' --stored foo='bar\n' > /dev/null
+
+  $ git init -q repo
+
+A bundle that can be applied passes the check, and nothing is stored.
+
+  $ git -C repo cinnabar unbundle --check $(pwd)/good.hg 2> /dev/null
+  $ git -C repo cinnabar hg2git $CS
+  0000000000000000000000000000000000000000
+  $ git -C repo rev-parse -q --verify refs/cinnabar/metadata
+  [1]
+
+A bundle with a corrupted file revision fails the check, without aborting.
+
+  $ git -C repo cinnabar unbundle --check $(pwd)/bad.hg 2>&1 | grep -o 'ERROR .*'
+  ERROR Error in file 2ed2a3912a0b24502043eae84ee4b279c18b90dd
+  $ git -C repo cinnabar unbundle --check $(pwd)/bad.hg > /dev/null 2>&1
+  [1]
+
+A truncated bundle fails the check too.
+
+  $ head -c 5 good.hg > truncated.hg
+  $ git -C repo cinnabar unbundle --check $(pwd)/truncated.hg
+  \r (no-eol) (esc)
+  ERROR Cannot read bundle: failed to fill whole buffer
+  [1]
+
+The bundle that passed the check can then be applied.
+
+  $ git -C repo cinnabar unbundle $(pwd)/good.hg > /dev/null 2>&1
+  $ test "$(git -C repo cinnabar git2hg $(git -C repo cinnabar hg2git $CS))" = "$CS"